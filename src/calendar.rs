@@ -0,0 +1,233 @@
+use chrono::{Datelike, NaiveDate, TimeDelta, Weekday};
+
+/// Returns whether the given date is a Swedish holiday, i.e. a public holiday (helgdag) or one
+/// of midsummer eve, Christmas eve and New Year's eve which in practice are treated as holidays
+/// (e.g. by grid operators in their time-of-use tariffs)
+///
+/// # Arguments
+///
+/// * 'date' - the date to check
+pub fn is_swedish_holiday(date: NaiveDate) -> bool {
+    swedish_holidays(date.year()).contains(&date)
+}
+
+/// Returns all Swedish holidays, including midsummer, Christmas and New Year's eve, for a year
+///
+/// # Arguments
+///
+/// * 'year' - the year to get holidays for
+pub fn swedish_holidays(year: i32) -> Vec<NaiveDate> {
+    let date = |month: u32, day: u32| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+    let easter = easter_sunday(year);
+
+    vec![
+        date(1, 1),                                 // Nyårsdagen
+        date(1, 6),                                 // Trettondedag jul
+        easter - TimeDelta::days(2),                // Långfredagen
+        easter,                                     // Påskdagen
+        easter + TimeDelta::days(1),                // Annandag påsk
+        date(5, 1),                                 // Första maj
+        easter + TimeDelta::days(39),               // Kristi himmelsfärdsdag
+        easter + TimeDelta::days(49),               // Pingstdagen
+        date(6, 6),                                 // Sveriges nationaldag
+        first_weekday_from(date(6, 19), Weekday::Fri), // Midsommarafton
+        first_weekday_from(date(6, 20), Weekday::Sat), // Midsommardagen
+        first_weekday_from(date(10, 31), Weekday::Sat), // Alla helgons dag
+        date(12, 24),                               // Julafton
+        date(12, 25),                               // Juldagen
+        date(12, 26),                               // Annandag jul
+        date(12, 31),                               // Nyårsafton
+    ]
+}
+
+/// All-day event read from an iCalendar file, given by its first and last day (inclusive)
+///
+pub struct AllDayEvent {
+    pub first: NaiveDate,
+    pub last: NaiveDate,
+    pub summary: String,
+}
+
+/// Returns all all-day events in iCalendar (ICS) content. Events with a start time are skipped
+/// and recurring events are not expanded. Since the end date of an all-day event is exclusive,
+/// the last day of an event is the day before its end date.
+///
+/// # Arguments
+///
+/// * 'ics' - content of an ICS file
+pub fn all_day_events(ics: &str) -> Vec<AllDayEvent> {
+    // Unfold lines, a line starting with a space or tab continues the previous line
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continued), Some(last)) => last.push_str(continued),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut events: Vec<AllDayEvent> = Vec::new();
+    let mut start: Option<NaiveDate> = None;
+    let mut end: Option<NaiveDate> = None;
+    let mut summary = String::new();
+    let mut in_event = false;
+
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else { continue };
+        let property = name.split(';').next().unwrap_or_default().to_uppercase();
+
+        match property.as_str() {
+            "BEGIN" if value.trim() == "VEVENT" => {
+                in_event = true;
+                start = None;
+                end = None;
+                summary.clear();
+            },
+            "DTSTART" if in_event => start = NaiveDate::parse_from_str(value.trim(), "%Y%m%d").ok(),
+            "DTEND" if in_event => end = NaiveDate::parse_from_str(value.trim(), "%Y%m%d").ok(),
+            "SUMMARY" if in_event => summary = value.trim().replace("\\,", ",").replace("\\;", ";"),
+            "END" if value.trim() == "VEVENT" => {
+                if let Some(first) = start {
+                    let last = end.map(|e| e - TimeDelta::days(1)).unwrap_or(first).max(first);
+                    events.push(AllDayEvent { first, last, summary: summary.clone() });
+                }
+                in_event = false;
+            },
+            _ => (),
+        }
+    }
+
+    events
+}
+
+/// Returns the date of Easter Sunday for a given year using the anonymous Gregorian algorithm
+///
+/// # Arguments
+///
+/// * 'year' - the year to get Easter Sunday for
+fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).unwrap()
+}
+
+/// Returns the first date on or after the given date that falls on the given weekday
+///
+/// # Arguments
+///
+/// * 'date' - the date to start from
+/// * 'weekday' - the weekday to find
+fn first_weekday_from(date: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let offset = (7 + weekday.num_days_from_monday() - date.weekday().num_days_from_monday()) % 7;
+    date + TimeDelta::days(offset as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn easter_sunday_on_known_dates() {
+        assert_eq!(easter_sunday(2024), date(2024, 3, 31));
+        assert_eq!(easter_sunday(2025), date(2025, 4, 20));
+        assert_eq!(easter_sunday(2026), date(2026, 4, 5));
+    }
+
+    #[test]
+    fn swedish_holidays_on_known_dates() {
+        let holidays = swedish_holidays(2025);
+
+        assert_eq!(holidays.len(), 16);
+        for holiday in [
+            date(2025, 4, 18),  // Långfredagen
+            date(2025, 4, 21),  // Annandag påsk
+            date(2025, 5, 29),  // Kristi himmelsfärdsdag
+            date(2025, 6, 8),   // Pingstdagen
+            date(2025, 6, 20),  // Midsommarafton
+            date(2025, 6, 21),  // Midsommardagen
+            date(2025, 11, 1),  // Alla helgons dag
+            date(2025, 12, 31), // Nyårsafton
+        ] {
+            assert!(holidays.contains(&holiday), "{} should be a holiday", holiday);
+        }
+    }
+
+    #[test]
+    fn midsummer_eve_is_the_friday_between_june_19_and_25() {
+        assert!(is_swedish_holiday(date(2026, 6, 19)));
+        assert!(is_swedish_holiday(date(2026, 6, 20)));
+        assert!(!is_swedish_holiday(date(2026, 6, 26)));
+        assert!(!is_swedish_holiday(date(2026, 6, 18)));
+    }
+
+    #[test]
+    fn all_day_events_unfold_lines_and_treat_end_date_as_exclusive() {
+        let ics = "BEGIN:VCALENDAR\r\n\
+            BEGIN:VEVENT\r\n\
+            DTSTART;VALUE=DATE:20250714\r\n\
+            DTEND;VALUE=DATE:20250718\r\n\
+            SUMMARY:Summer\r\n \
+            house\\, week 29\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\n\
+            DTSTART;VALUE=DATE:20251224\r\n\
+            DTEND;VALUE=DATE:20251225\r\n\
+            SUMMARY:Sunday\r\n\
+            END:VEVENT\r\n\
+            BEGIN:VEVENT\r\n\
+            DTSTART;VALUE=DATE:20251231\r\n\
+            SUMMARY:No end\r\n\
+            END:VEVENT\r\n\
+            END:VCALENDAR\r\n";
+        let events = all_day_events(ics);
+
+        assert_eq!(events.len(), 3);
+        assert_eq!((events[0].first, events[0].last), (date(2025, 7, 14), date(2025, 7, 17)));
+        assert_eq!(events[0].summary, "Summerhouse, week 29");
+        assert_eq!((events[1].first, events[1].last), (date(2025, 12, 24), date(2025, 12, 24)));
+        assert_eq!(events[1].summary, "Sunday");
+        assert_eq!((events[2].first, events[2].last), (date(2025, 12, 31), date(2025, 12, 31)));
+    }
+
+    #[test]
+    fn all_day_events_skip_timed_events() {
+        let ics = "BEGIN:VCALENDAR\n\
+            BEGIN:VEVENT\n\
+            DTSTART:20250301T080000Z\n\
+            DTEND:20250301T090000Z\n\
+            SUMMARY:Meeting\n\
+            END:VEVENT\n\
+            BEGIN:VEVENT\n\
+            DTSTART;TZID=Europe/Stockholm:20250302T100000\n\
+            DTEND;TZID=Europe/Stockholm:20250302T120000\n\
+            SUMMARY:Lunch\n\
+            END:VEVENT\n\
+            BEGIN:VEVENT\n\
+            DTSTART;VALUE=DATE:20250303\n\
+            DTEND;VALUE=DATE:20250304\n\
+            SUMMARY:Away\n\
+            END:VEVENT\n\
+            END:VCALENDAR\n";
+        let events = all_day_events(ics);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].first, date(2025, 3, 3));
+        assert_eq!(events[0].summary, "Away");
+    }
+}
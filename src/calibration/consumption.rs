@@ -0,0 +1,523 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Add;
+use chrono::{Datelike, Local, NaiveDate, TimeDelta, Timelike};
+use foxess::{Fox, FoxVariables};
+use log::info;
+use crate::calibration::{fit_statistics, CalibrationError};
+use crate::calibration::history::{fox_history, load_forecasts, load_history_csv, HourlyValues};
+use crate::config::{check_consumption_curve, Config, ConsumptionParameters, CurveResolution};
+use crate::consumption::{Consumption, WEEKDAYS};
+use crate::spline::MonotonicCubicSpline;
+
+/// Mean, standard deviation and number of samples for one weekday and hour slot
+struct SlotStatistics {
+    mean: f64,
+    std_dev: f64,
+    samples: usize,
+}
+
+/// Learns the weekly consumption diagram from household consumption history. The temperature
+/// driven part given by the consumption curve is removed from each hourly mean consumption, and
+/// what remains is averaged per local weekday and hour, separately for the default diagram and
+/// each seasonal variant. Days with a calendar override or holiday day profile are skipped, and
+/// day profiles are carried over as they are. Consumption history is read from a CSV file or, if not given, from Fox
+/// ESS load power history, and temperatures from a CSV file or, if not given, from saved base data
+/// forecasts. A new consumption diagram file and a report with per slot variance are written to
+/// the output directory. Diagrams and day profiles with quarter hour detail are written with 96
+/// values per row, where the learned hourly mean is spread over the quarters following the
+/// current quarter hour shape, otherwise with 24 hourly values per row.
+///
+/// # Arguments
+///
+/// * 'config' - configuration
+/// * 'fox' - Fox ESS client, used if no consumption history file is configured
+pub fn learn_consumption_diagram(config: &Config, fox: &Fox) -> Result<(), CalibrationError> {
+    let output_dir = config.calibration.output_dir.as_ref().unwrap_or(&config.files.base_data_dir);
+    let current = config.consumption.diagram.as_ref()
+        .ok_or(CalibrationError::NoContent("no consumption diagram loaded".to_string()))?;
+
+    let (temperatures, consumption) = consumption_history(config, fox)?;
+    let cons = Consumption::new(&config.consumption);
+
+    // Weekday and hour slots for the default diagram followed by one set per seasonal variant
+    let mut slots: Vec<Vec<Vec<Vec<f64>>>> = vec![vec![vec![Vec::new(); 24]; 7]; current.seasons.len() + 1];
+    let mut hours = 0usize;
+    let mut skipped = 0usize;
+    for (hour, load) in consumption.iter() {
+        let Some(temp) = temperatures.get(hour) else { continue };
+        let local = hour.with_timezone(&Local);
+        if current.profile(local.date_naive()).is_some() {
+            skipped += 1;
+            continue;
+        }
+        let profile = current.season(local.date_naive()).map(|s| s + 1).unwrap_or(0);
+        slots[profile][local.weekday().num_days_from_monday() as usize][local.hour() as usize]
+            .push(load * 1000.0 - cons.consumption_curve(*temp)); // kW to W
+        hours += 1;
+    }
+
+    if hours == 0 {
+        return Err(CalibrationError::NoContent("no hours with both consumption and temperature".to_string()));
+    }
+    info!("Learning consumption diagram from {} hours, {} hours with day profiles skipped", hours, skipped);
+
+    let statistics: Vec<Vec<Vec<Option<SlotStatistics>>>> = slots.iter()
+        .map(|profile| profile.iter().map(|day| day.iter().map(|slot| slot_statistics(slot)).collect()).collect())
+        .collect();
+    let profiles: Vec<(&str, &[[f64;96];7])> = [("consumption_diagram", &current.days)].into_iter()
+        .chain(current.seasons.iter().map(|s| (s.name.as_str(), &s.days)))
+        .collect();
+
+    let period = format!("{} - {}",
+        consumption.keys().next().map(|t| t.with_timezone(&Local).date_naive().to_string()).unwrap_or_default(),
+        consumption.keys().last().map(|t| t.with_timezone(&Local).date_naive().to_string()).unwrap_or_default());
+
+    let mut diagram = format!("# Learned {} from {} hours ({}), see consumption_diagram_report.txt\n", Local::now().format("%Y-%m-%d %H:%M"), hours, period);
+    diagram.push_str("[consumption_diagram]\n");
+    diagram.push_str(&diagram_rows(&statistics[0], &current.days));
+    for (season, statistics) in current.seasons.iter().zip(statistics.iter().skip(1)) {
+        diagram.push_str("\n[[seasonal_diagram]]\n");
+        diagram.push_str(&format!("name = \"{}\"\n", season.name));
+        diagram.push_str(&format!("from = \"{:02}-{:02}\"\n", season.from.0, season.from.1));
+        diagram.push_str(&format!("to   = \"{:02}-{:02}\"\n", season.to.0, season.to.1));
+        diagram.push_str("[seasonal_diagram.consumption_diagram]\n");
+        diagram.push_str(&diagram_rows(statistics, &season.days));
+    }
+    if !current.profiles.is_empty() {
+        let mut names = current.profiles.keys().collect::<Vec<&String>>();
+        names.sort();
+        diagram.push_str("\n[day_profiles]\n");
+        for name in names {
+            let day = &current.profiles[name];
+            let values: Vec<String> = if is_hourly(day) {
+                day.iter().step_by(4).map(|v| format!("{:.1}", v)).collect()
+            } else {
+                day.iter().map(|v| format!("{:.1}", v)).collect()
+            };
+            diagram.push_str(&format!("{} = [{}]\n", name, values.join(", ")));
+        }
+    }
+
+    let mut report = String::from("Consumption diagram report\n\n");
+    report.push_str(&format!("Created:      {}\n", Local::now().format("%Y-%m-%d %H:%M")));
+    report.push_str(&format!("Period:       {}\n", period));
+    report.push_str(&format!("Hours:        {}\n", hours));
+    report.push_str(&format!("Skipped:      {} hours on days with calendar overrides or holidays\n\n", skipped));
+    report.push_str("Load (W) above the consumption curve per weekday and hour. Slots without data keep the\n");
+    report.push_str("current diagram value, negative means are written as 0.0.\n");
+    for ((name, days), statistics) in profiles.iter().zip(statistics.iter()) {
+        report.push_str(&format!("\n{}\n", name));
+        for (d, day) in statistics.iter().enumerate() {
+            report.push_str(&format!("\n{}\n{:<6}{:>10}{:>10}{:>10}{:>10}\n", WEEKDAYS[d], "Hour", "mean", "std dev", "std err", "samples"));
+            for (h, s) in day.iter().enumerate() {
+                match s {
+                    Some(s) => report.push_str(&format!("{:<6}{:>10.1}{:>10.1}{:>10.1}{:>10}{}\n",
+                        h, s.mean, s.std_dev, s.std_dev / (s.samples as f64).sqrt(), s.samples,
+                        if s.mean < 0.0 { "   (negative)" } else { "" })),
+                    None => report.push_str(&format!("{:<6}{:>10}{:>10}{:>10}{:>10}   (no data, kept {:.1})\n", h, "-", "-", "-", 0, hour_mean(&days[d], h))),
+                }
+            }
+        }
+    }
+
+    fs::create_dir_all(output_dir)?;
+    fs::write(format!("{}consumption_diagram.toml", output_dir), diagram)?;
+    fs::write(format!("{}consumption_diagram_report.txt", output_dir), report)?;
+    info!("Consumption diagram saved to {}", output_dir);
+
+    Ok(())
+}
+
+/// Returns weekday rows for a consumption diagram file, where slots without data keep the current
+/// value. Rows have 24 hourly values if every current day is flat within each hour, otherwise 96
+/// quarter hour values, where the learned hourly mean is spread over the quarters in proportion
+/// to the current quarter hour values.
+///
+/// # Arguments
+///
+/// * 'statistics' - learned statistics per weekday and hour
+/// * 'current' - current quarter hour values per weekday
+fn diagram_rows(statistics: &[Vec<Option<SlotStatistics>>], current: &[[f64;96];7]) -> String {
+    let quarters = !current.iter().all(is_hourly);
+    let mut rows = if quarters {
+        format!("{:<13}{}\n", "# Quarter:", (0..96).map(|q| format!("{:>7}", format!("{:02}:{:02}", q / 4, q % 4 * 15))).collect::<Vec<String>>().join("  "))
+    } else {
+        format!("{:<13}{}\n", "# Hour:", (0..24).map(|h| format!("{:>7}", h)).collect::<Vec<String>>().join("  "))
+    };
+    for (d, day) in statistics.iter().enumerate() {
+        let values = day.iter()
+            .enumerate()
+            .flat_map(|(h, s)| {
+                let mean = s.as_ref().map(|s| s.mean.max(0.0)).unwrap_or(hour_mean(&current[d], h));
+                if quarters { hour_quarters(&current[d], h, mean).to_vec() } else { vec![mean] }
+            })
+            .map(|v| format!("{:>7.1}", v))
+            .collect::<Vec<String>>();
+        rows.push_str(&format!("{:<10}= [{}]\n", WEEKDAYS[d], values.join(", ")));
+    }
+
+    rows
+}
+
+/// Returns true if all quarter hour values within each hour of the day are equal
+///
+/// # Arguments
+///
+/// * 'day' - quarter hour values for a day
+fn is_hourly(day: &[f64;96]) -> bool {
+    day.chunks(4).all(|q| q.iter().all(|v| *v == q[0]))
+}
+
+/// Returns the quarter hour values for an hour with the given mean, following the shape of the
+/// current quarter hour values, or evenly spread if the current hour has no load
+///
+/// # Arguments
+///
+/// * 'day' - current quarter hour values for a day
+/// * 'hour' - hour of the day
+/// * 'mean' - new mean for the hour
+fn hour_quarters(day: &[f64;96], hour: usize, mean: f64) -> [f64;4] {
+    let current = hour_mean(day, hour);
+    let mut quarters = [mean;4];
+    if current > 0.0 {
+        quarters.iter_mut()
+            .zip(day[hour * 4..hour * 4 + 4].iter())
+            .for_each(|(q, v)| *q = v * mean / current);
+    }
+
+    quarters
+}
+
+/// Returns the mean of the quarter hour values within an hour
+///
+/// # Arguments
+///
+/// * 'day' - quarter hour values for a day
+/// * 'hour' - hour of the day
+fn hour_mean(day: &[f64;96], hour: usize) -> f64 {
+    day[hour * 4..hour * 4 + 4].iter().sum::<f64>() / 4.0
+}
+
+/// Fits the consumption curve, min_avg_load and max_avg_load to household consumption against
+/// outdoor temperature. The consumption diagram is removed from each hourly mean consumption, and
+/// what remains is, at daily or hourly resolution, fitted to temperature by a non-increasing
+/// (isotonic) regression. The regression is sampled at evenly spaced temperatures over the
+/// observed range, where the highest load gives max_avg_load, the lowest load gives min_avg_load
+/// and the loads in between are normalized to an index going from 1.0 at the lowest temperature
+/// to 0.0 at the highest. A suggested consumption block and a fit report are written to the
+/// output directory.
+///
+/// # Arguments
+///
+/// * 'config' - configuration
+/// * 'fox' - Fox ESS client, used if no consumption history file is configured
+pub fn fit_consumption_curve(config: &Config, fox: &Fox) -> Result<(), CalibrationError> {
+    let output_dir = config.calibration.output_dir.as_ref().unwrap_or(&config.files.base_data_dir);
+    let diagram = config.consumption.diagram.as_ref()
+        .ok_or(CalibrationError::NoContent("no consumption diagram loaded".to_string()))?;
+    let resolution = config.calibration.curve_resolution;
+    let points = config.calibration.curve_points.max(2);
+
+    let (temperatures, consumption) = consumption_history(config, fox)?;
+
+    // Temperature and load above the consumption diagram (W), per hour or per local day
+    let mut hourly: Vec<(f64, f64)> = Vec::new();
+    let mut days: BTreeMap<NaiveDate, (f64, f64, usize)> = BTreeMap::new();
+    for (hour, load) in consumption.iter() {
+        let Some(temp) = temperatures.get(hour) else { continue };
+        let local = hour.with_timezone(&Local);
+        let base = load * 1000.0 - hour_mean(diagram.day(local.date_naive()), local.hour() as usize); // kW to W
+        hourly.push((*temp, base));
+        let _ = days
+            .entry(local.date_naive())
+            .and_modify(|d| { d.0 += temp; d.1 += base; d.2 += 1; })
+            .or_insert((*temp, base, 1));
+    }
+
+    let mut samples: Vec<(f64, f64)> = match resolution {
+        CurveResolution::Hourly => hourly,
+        CurveResolution::Daily => days.values()
+            .filter(|d| d.2 >= 20)
+            .map(|d| (d.0 / d.2 as f64, d.1 / d.2 as f64))
+            .collect(),
+    };
+    samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    if samples.len() < points {
+        return Err(CalibrationError::NoContent(format!("{} samples with both consumption and temperature, need at least {}", samples.len(), points)));
+    }
+    let (t_min, t_max) = (samples[0].0, samples[samples.len() - 1].0);
+    if t_max - t_min < 0.5 * (points - 1) as f64 {
+        return Err(CalibrationError::NoContent(format!("temperature range {:.1} to {:.1} is too narrow for {} curve points", t_min, t_max, points)));
+    }
+    info!("Fitting consumption curve to {} {:?} samples", samples.len(), resolution);
+
+    // Sample the regression at evenly spaced temperatures and normalize the loads to an index
+    let fitted = non_increasing_fit(&samples);
+    let knots: Vec<(f64, f64)> = (0..points)
+        .map(|i| {
+            let temp = ((t_min + (t_max - t_min) * i as f64 / (points - 1) as f64) * 10.0).round() / 10.0;
+            (temp, interpolate(&samples, &fitted, temp))
+        })
+        .collect();
+
+    let min_avg_load = knots[points - 1].1.max(0.0);
+    let max_avg_load = knots[0].1.max(min_avg_load);
+    let span = max_avg_load - min_avg_load;
+
+    let mut curve: Vec<(f64, f64)> = Vec::with_capacity(points);
+    for (i, (temp, load)) in knots.iter().enumerate() {
+        let index = if span > 0.0 {
+            ((load - min_avg_load) / span * 1000.0).round() / 1000.0
+        } else {
+            1.0 - i as f64 / (points - 1) as f64
+        };
+        let previous = curve.last().map(|c| c.1).unwrap_or(1.0);
+        curve.push((*temp, index.clamp(0.0, previous)));
+    }
+    curve[0].1 = 1.0;
+    curve[points - 1].1 = 0.0;
+
+    check_consumption_curve(&curve)?;
+    let (curve_x, curve_y): (Vec<f64>, Vec<f64>) = curve.iter().copied().unzip();
+    MonotonicCubicSpline::new(&curve_x, &curve_y)?;
+
+    // Compare the current and the fitted curve on the samples
+    let current = Consumption::new(&config.consumption);
+    let suggested = Consumption::new(&ConsumptionParameters {
+        min_avg_load,
+        max_avg_load,
+        curve: curve.clone(),
+        calendar: config.consumption.calendar.clone(),
+        diagram: config.consumption.diagram.clone(),
+    });
+    let loads: Vec<f64> = samples.iter().map(|s| s.1).collect();
+    let before = fit_statistics(&samples.iter().map(|s| current.consumption_curve(s.0) - s.1).collect::<Vec<f64>>(), &loads);
+    let after = fit_statistics(&samples.iter().map(|s| suggested.consumption_curve(s.0) - s.1).collect::<Vec<f64>>(), &loads);
+    info!("Consumption curve RMSE {:.1} W -> {:.1} W", before.rmse, after.rmse);
+
+    let period = format!("{} - {}",
+        consumption.keys().next().map(|t| t.with_timezone(&Local).date_naive().to_string()).unwrap_or_default(),
+        consumption.keys().last().map(|t| t.with_timezone(&Local).date_naive().to_string()).unwrap_or_default());
+    let curve_toml = curve.iter().map(|(t, i)| format!("[{:.1}, {:?}]", t, i)).collect::<Vec<String>>().join(",");
+
+    let mut suggestion = format!("# Suggested consumption parameters, fitted {} to {} {:?} samples ({})\n", Local::now().format("%Y-%m-%d %H:%M"), samples.len(), resolution, period);
+    suggestion.push_str(&format!("# RMSE {:.1} W -> {:.1} W, see consumption_curve_report.txt\n", before.rmse, after.rmse));
+    suggestion.push_str("[consumption]\n");
+    suggestion.push_str(&format!("min_avg_load = {:.1}\n", min_avg_load));
+    suggestion.push_str(&format!("max_avg_load = {:.1}\n", max_avg_load));
+    suggestion.push_str(&format!("curve = [{}]\n", curve_toml));
+
+    let mut report = String::from("Consumption curve report\n\n");
+    report.push_str(&format!("Created:      {}\n", Local::now().format("%Y-%m-%d %H:%M")));
+    report.push_str(&format!("Period:       {}\n", period));
+    report.push_str(&format!("Resolution:   {:?}\n", resolution));
+    report.push_str(&format!("Samples:      {}\n", samples.len()));
+    report.push_str(&format!("Temperatures: {:.1} to {:.1}\n\n", t_min, t_max));
+    report.push_str(&format!("{:<14}{:>10}{:>10}\n", "", "current", "fitted"));
+    report.push_str(&format!("{:<14}{:>10.1}{:>10.1}\n", "min_avg_load", config.consumption.min_avg_load, min_avg_load));
+    report.push_str(&format!("{:<14}{:>10.1}{:>10.1}\n\n", "max_avg_load", config.consumption.max_avg_load, max_avg_load));
+    report.push_str("Load (W) above the consumption diagram, negative residuals mean the curve is below the load\n");
+    report.push_str(&format!("{:<14}{:>10}{:>10}\n", "Statistic", "current", "fitted"));
+    report.push_str(&format!("{:<14}{:>10.1}{:>10.1}\n", "RMSE", before.rmse, after.rmse));
+    report.push_str(&format!("{:<14}{:>10.1}{:>10.1}\n", "MAE", before.mae, after.mae));
+    report.push_str(&format!("{:<14}{:>10.1}{:>10.1}\n", "Bias", before.bias, after.bias));
+    report.push_str(&format!("{:<14}{:>10.3}{:>10.3}\n\n", "R2", before.r2, after.r2));
+    report.push_str(&format!("{:<14}{:>10}{:>10}{:>10}\n", "Temperature", "load", "index", "curve"));
+    for ((temp, load), (_, index)) in knots.iter().zip(curve.iter()) {
+        report.push_str(&format!("{:<14.1}{:>10.1}{:>10.3}{:>10.1}\n", temp, load, index, suggested.consumption_curve(*temp)));
+    }
+
+    fs::create_dir_all(output_dir)?;
+    fs::write(format!("{}consumption_curve.toml", output_dir), suggestion)?;
+    fs::write(format!("{}consumption_curve_report.txt", output_dir), report)?;
+    info!("Consumption curve saved to {}", output_dir);
+
+    Ok(())
+}
+
+/// Returns hourly mean temperatures and hourly mean consumption (kW) from configured history
+/// files, or from saved base data forecasts and Fox ESS history if no files are configured
+///
+/// # Arguments
+///
+/// * 'config' - configuration
+/// * 'fox' - Fox ESS client
+fn consumption_history(config: &Config, fox: &Fox) -> Result<(HourlyValues, HourlyValues), CalibrationError> {
+    let temperatures: HourlyValues = match config.calibration.temperature_history.as_ref() {
+        Some(path) => load_history_csv(path)?,
+        None => {
+            let base_data_dir = config.calibration.base_data_dir.as_ref().unwrap_or(&config.files.base_data_dir);
+            load_forecasts(base_data_dir)?.into_iter().map(|(t, f)| (t, f.temp)).collect()
+        },
+    };
+
+    let consumption = match config.calibration.consumption_history.as_ref() {
+        Some(path) => load_history_csv(path)?,
+        None => {
+            let (Some(first), Some(last)) = (temperatures.keys().next(), temperatures.keys().last()) else {
+                return Err(CalibrationError::NoContent("no temperatures to get consumption history for".to_string()));
+            };
+            fox_history(fox, FoxVariables::LoadsPower, *first, last.add(TimeDelta::hours(1)))?
+        },
+    };
+
+    Ok((temperatures, consumption))
+}
+
+/// Returns mean, sample standard deviation and number of samples, or None if there are no samples
+///
+/// # Arguments
+///
+/// * 'values' - samples for one slot
+fn slot_statistics(values: &[f64]) -> Option<SlotStatistics> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = if values.len() > 1 {
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)
+    } else {
+        0.0
+    };
+
+    Some(SlotStatistics { mean, std_dev: variance.sqrt(), samples: values.len() })
+}
+
+/// Returns the non-increasing least squares fit (isotonic regression) of the loads using the pool
+/// adjacent violators algorithm, one fitted value per sample
+///
+/// # Arguments
+///
+/// * 'samples' - temperature and load pairs sorted by temperature
+fn non_increasing_fit(samples: &[(f64, f64)]) -> Vec<f64> {
+    // Blocks of pooled samples as sum of loads and number of samples
+    let mut blocks: Vec<(f64, usize)> = Vec::with_capacity(samples.len());
+    for (_, load) in samples {
+        blocks.push((*load, 1));
+        while blocks.len() > 1 {
+            let (sum, count) = blocks[blocks.len() - 1];
+            let (prev_sum, prev_count) = blocks[blocks.len() - 2];
+            if prev_sum / prev_count as f64 >= sum / count as f64 {
+                break;
+            }
+            blocks.pop();
+            let last = blocks.len() - 1;
+            blocks[last] = (prev_sum + sum, prev_count + count);
+        }
+    }
+
+    blocks.iter()
+        .flat_map(|(sum, count)| std::iter::repeat_n(sum / *count as f64, *count))
+        .collect()
+}
+
+/// Returns the fitted load at a temperature by linear interpolation between samples
+///
+/// # Arguments
+///
+/// * 'samples' - temperature and load pairs sorted by temperature
+/// * 'fitted' - fitted load per sample
+/// * 'temp' - temperature to get the fitted load for
+fn interpolate(samples: &[(f64, f64)], fitted: &[f64], temp: f64) -> f64 {
+    let i = samples.partition_point(|s| s.0 < temp);
+    if i == 0 {
+        return fitted[0];
+    }
+    if i == samples.len() {
+        return fitted[samples.len() - 1];
+    }
+
+    let (t0, t1) = (samples[i - 1].0, samples[i].0);
+    if t1 - t0 <= 0.0 {
+        return fitted[i];
+    }
+    fitted[i - 1] + (fitted[i] - fitted[i - 1]) * (temp - t0) / (t1 - t0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_increasing_fit_pools_adjacent_violators() {
+        let samples = [(-5.0, 900.0), (0.0, 700.0), (5.0, 800.0), (10.0, 300.0), (15.0, 100.0), (20.0, 200.0)];
+        let fitted = non_increasing_fit(&samples);
+
+        assert_eq!(fitted, vec![900.0, 750.0, 750.0, 300.0, 150.0, 150.0]);
+        assert!(fitted.windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(interpolate(&samples, &fitted, -10.0), 900.0);
+        assert_eq!(interpolate(&samples, &fitted, 7.5), 525.0);
+        assert_eq!(interpolate(&samples, &fitted, 25.0), 150.0);
+    }
+
+    #[test]
+    fn non_increasing_fit_keeps_a_non_increasing_series() {
+        let samples = [(0.0, 500.0), (1.0, 400.0), (2.0, 400.0), (3.0, 100.0)];
+
+        assert_eq!(non_increasing_fit(&samples), vec![500.0, 400.0, 400.0, 100.0]);
+    }
+
+    #[test]
+    fn slot_statistics_gives_mean_sample_std_dev_and_count() {
+        let s = slot_statistics(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]).unwrap();
+
+        assert_eq!(s.mean, 5.0);
+        assert!((s.std_dev - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        assert_eq!(s.samples, 8);
+
+        let single = slot_statistics(&[3.0]).unwrap();
+        assert_eq!((single.mean, single.std_dev, single.samples), (3.0, 0.0, 1));
+        assert!(slot_statistics(&[]).is_none());
+    }
+
+    /// Returns learned statistics with the given mean for one hour on Monday and no data elsewhere
+    fn monday_hour(hour: usize, mean: f64) -> Vec<Vec<Option<SlotStatistics>>> {
+        (0..7).map(|d| (0..24)
+            .map(|h| (d == 0 && h == hour).then_some(SlotStatistics { mean, std_dev: 0.0, samples: 1 }))
+            .collect())
+            .collect()
+    }
+
+    /// Returns the values of the Monday row in diagram rows
+    fn monday_values(rows: &str) -> Vec<f64> {
+        let row = rows.lines().find(|l| l.starts_with(WEEKDAYS[0])).unwrap();
+        let values = row.split_once('[').unwrap().1.trim_end_matches(']');
+        values.split(',').map(|v| v.trim().parse::<f64>().unwrap()).collect()
+    }
+
+    #[test]
+    fn diagram_rows_are_hourly_for_hourly_diagrams() {
+        let current = [[100.0;96];7];
+        let values = monday_values(&diagram_rows(&monday_hour(8, 400.0), &current));
+
+        assert_eq!(values.len(), 24);
+        assert_eq!(values[8], 400.0);
+        assert_eq!(values[9], 100.0);
+    }
+
+    #[test]
+    fn diagram_rows_keep_quarter_hour_shape() {
+        let mut current = [[100.0;96];7];
+        current[3][40..44].copy_from_slice(&[0.0, 100.0, 200.0, 300.0]);
+        current[0][32..36].copy_from_slice(&[100.0, 200.0, 300.0, 400.0]);
+        let values = monday_values(&diagram_rows(&monday_hour(8, 500.0), &current));
+
+        assert_eq!(values.len(), 96);
+        assert_eq!(&values[32..36], &[200.0, 400.0, 600.0, 800.0]);
+        assert_eq!(&values[36..40], &[100.0;4]);
+
+        let values = monday_values(&diagram_rows(&monday_hour(8, -50.0), &current));
+        assert_eq!(&values[32..36], &[0.0;4]);
+    }
+
+    #[test]
+    fn hour_quarters_spread_evenly_without_current_load() {
+        let day = [0.0;96];
+
+        assert_eq!(hour_quarters(&day, 5, 250.0), [250.0;4]);
+    }
+}
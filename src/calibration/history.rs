@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::{fs, thread};
+use std::ops::Add;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use foxess::{Fox, FoxVariables};
+use glob::glob;
+use log::warn;
+use serde::Deserialize;
+use crate::calibration::CalibrationError;
+use crate::models::ForecastValue;
+use crate::{retry, wrapper};
+
+/// Hourly mean values keyed by the start of each hour
+pub type HourlyValues = BTreeMap<DateTime<Utc>, f64>;
+
+/// The part of a saved base data file needed for calibration. Only the forecast is read so that
+/// base data files saved by older versions, lacking later added fields, can be used as well.
+#[derive(Deserialize)]
+struct BaseDataForecast {
+    forecast: Vec<ForecastValue>,
+}
+
+/// Loads forecasts from all saved base data files in a directory. Where forecasts overlap, the
+/// one from the latest run is kept since it has the shortest lead time. Files that can't be read
+/// or parsed are skipped with a warning.
+///
+/// # Arguments
+///
+/// * 'dir' - directory with base data files
+pub fn load_forecasts(dir: &str) -> Result<BTreeMap<DateTime<Utc>, ForecastValue>, CalibrationError> {
+    let pattern = format!("{}*_base_data.json", dir);
+    let mut paths = glob(&pattern)
+        .map_err(|e| CalibrationError::File(format!("error reading files with pattern {}: {}", pattern, e)))?
+        .filter_map(|p| p.ok())
+        .collect::<Vec<_>>();
+    paths.sort();
+
+    let mut forecasts: BTreeMap<DateTime<Utc>, ForecastValue> = BTreeMap::new();
+    for path in paths {
+        let base_data = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str::<BaseDataForecast>(&json).map_err(|e| e.to_string()));
+        match base_data {
+            Ok(base_data) => base_data.forecast.into_iter().for_each(|f| { forecasts.insert(f.valid_time, f); }),
+            Err(e) => warn!("skipping unreadable base data file {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(forecasts)
+}
+
+/// Loads history from a CSV file with lines of timestamp (RFC 3339) and value, e.g. power (kW)
+/// or temperature, and returns it as hourly means. A header line, empty lines and lines starting
+/// with # are skipped.
+///
+/// # Arguments
+///
+/// * 'path' - path to the CSV file
+pub fn load_history_csv(path: &str) -> Result<HourlyValues, CalibrationError> {
+    let content = fs::read_to_string(path)?;
+    let mut values: Vec<(DateTime<Utc>, f64)> = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line.starts_with("timestamp")) {
+            continue;
+        }
+
+        let (timestamp, value) = line.split_once([',', ';'])
+            .ok_or(CalibrationError::Parse(format!("{} line {}: expected timestamp and value", path, i + 1)))?;
+        let timestamp = DateTime::parse_from_rfc3339(timestamp.trim())
+            .map_err(|e| CalibrationError::Parse(format!("{} line {}: {}", path, i + 1, e)))?
+            .with_timezone(&Utc);
+        let value = value.trim().parse::<f64>()
+            .map_err(|e| CalibrationError::Parse(format!("{} line {}: {}", path, i + 1, e)))?;
+
+        values.push((timestamp, value));
+    }
+
+    Ok(hourly_means(values))
+}
+
+/// Retrieves the history of a variable from Fox ESS, one day at a time, and returns it as hourly means
+///
+/// # Arguments
+///
+/// * 'fox' - Fox ESS client
+/// * 'variable' - the variable to retrieve, e.g. PV power or load power
+/// * 'from' - start of the period to retrieve
+/// * 'to' - end of the period to retrieve (non-inclusive)
+pub fn fox_history(fox: &Fox, variable: FoxVariables, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<HourlyValues, CalibrationError> {
+    let mut values: Vec<(DateTime<Utc>, f64)> = Vec::new();
+    let mut start = from;
+
+    while start < to {
+        let end = start.add(TimeDelta::hours(24)).min(to);
+        let history = retry!(||fox.get_variables_history(start, end, vec![variable]))?;
+        history.get(variable)
+            .unwrap_or_default()
+            .iter()
+            .for_each(|d| values.push((d.date_time, d.data)));
+        start = end;
+    }
+
+    Ok(hourly_means(values))
+}
+
+/// Groups values into hourly means
+///
+/// # Arguments
+///
+/// * 'values' - values with their time
+fn hourly_means(values: Vec<(DateTime<Utc>, f64)>) -> HourlyValues {
+    let mut hours: BTreeMap<DateTime<Utc>, (f64, f64)> = BTreeMap::new();
+    for (date_time, value) in values {
+        if let Ok(hour) = date_time.duration_trunc(TimeDelta::hours(1)) {
+            let _ = hours
+                .entry(hour)
+                .and_modify(|v| { v.0 += value; v.1 += 1.0; })
+                .or_insert((value, 1.0));
+        }
+    }
+
+    hours.into_iter().map(|(hour, (sum, count))| (hour, sum / count)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_forecasts_from_older_and_skips_unreadable_base_data() {
+        let dir = std::env::temp_dir().join(format!("mygrid_history_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // Saved before residual_value and the tariffs' variable_fee were added
+        let old = r#"{
+            "date_time": "2025-10-21T22:00:00Z", "base_cost": 12.3, "schedule_cost": 10.1, "soc_kwh": 0.15,
+            "forecast": [
+                {"valid_time": "2025-10-21T22:00:00Z", "temp": 8.2, "lcc_mean": 6.0, "mcc_mean": 3.0, "hcc_mean": 2.0, "cloud_factor": 0.4},
+                {"valid_time": "2025-10-21T23:00:00Z", "temp": 7.6, "lcc_mean": 7.0, "mcc_mean": 4.0, "hcc_mean": 1.0, "cloud_factor": 0.3}
+            ],
+            "production": [], "consumption": [],
+            "tariffs": [{"valid_time": "2025-10-21T22:00:00Z", "price": 0.41, "buy": 1.52, "sell": 0.49}],
+            "tariff_fees": {"variable_fee": 25.3, "spot_fee_percentage": 7.7, "energy_tax": 36.0, "swedish_power_grid": 1.833,
+                "balance_responsibility": 0.8, "electric_certificate": 1.6, "guarantees_of_origin": 3.0, "fixed": 2.4, "production_price": 7.5}
+        }"#;
+        fs::write(dir.join("202510212200_base_data.json"), old).unwrap();
+        fs::write(dir.join("202510222200_base_data.json"), "{ truncated").unwrap();
+
+        let forecasts = load_forecasts(&format!("{}/", dir.display()));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let forecasts = forecasts.unwrap();
+        assert_eq!(forecasts.len(), 2);
+        assert_eq!(forecasts.values().last().unwrap().temp, 7.6);
+    }
+}
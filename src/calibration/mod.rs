@@ -0,0 +1,282 @@
+mod consumption;
+mod history;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Add;
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use foxess::{Fox, FoxVariables};
+use log::info;
+use rayon::prelude::*;
+use anyhow::Result;
+use thiserror::Error;
+use crate::config::{Config, LoadConfigurationError, ProductionParameters};
+use crate::least_squares::{bounded_least_squares, LeastSquaresError};
+use crate::manager_forecast::CloudFactors;
+use crate::manager_production::{PVProduction, ProductionError};
+pub use crate::calibration::consumption::{fit_consumption_curve, learn_consumption_diagram};
+use crate::calibration::history::{fox_history, load_forecasts, load_history_csv, HourlyValues};
+use crate::models::{ForecastValue, ForecastValues};
+use crate::spline::SplineError;
+
+/// Production parameters fitted by calibration, given as name, lower bound and upper bound
+const FITTED_PARAMETERS: [(&str, f64, f64); 7] = [
+    ("tau", 0.25, 12.0),
+    ("k_gain", 0.0, 150.0),
+    ("iam_factor", 0.5, 5.0),
+    ("cloud_impact_factor", 0.0, 1.0),
+    ("low_clouds_factor", 0.0, 1.0),
+    ("mid_clouds_factor", 0.0, 1.0),
+    ("high_clouds_factor", 0.0, 1.0),
+];
+
+/// Least number of whole days to calibrate over, fewer days tend to fit the weather of those
+/// days rather than the production model
+const MIN_CALIBRATION_DAYS: usize = 7;
+
+/// One local day with both a saved forecast and actual PV production for every hour
+struct CalibrationDay {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    forecast: Vec<ForecastValue>,
+    actual: Vec<f64>,
+}
+
+/// Error statistics for a set of hourly residuals
+struct FitStatistics {
+    rmse: f64,
+    mae: f64,
+    bias: f64,
+    r2: f64,
+}
+
+/// Fits production parameters to actual PV production. Forecasts are read from saved base data
+/// files and actual PV power from a CSV file or, if not given, from Fox ESS history. The hourly
+/// difference between estimated and actual production is minimized by bounded least squares
+/// over the production model, and a suggested parameter block and a fit report are written to
+/// the output directory. At least MIN_CALIBRATION_DAYS whole days are needed, so base data
+/// has to be read from an archive since files in the base data directory are removed after 48 hours.
+///
+/// # Arguments
+///
+/// * 'config' - configuration
+/// * 'fox' - Fox ESS client, used if no PV history file is configured
+pub fn calibrate(config: &Config, fox: &Fox) -> Result<(), CalibrationError> {
+    let base_data_dir = config.calibration.base_data_dir.as_ref().unwrap_or(&config.files.base_data_dir);
+    let output_dir = config.calibration.output_dir.as_ref().unwrap_or(&config.files.base_data_dir);
+
+    let forecasts = load_forecasts(base_data_dir)?;
+    let (Some(first), Some(last)) = (forecasts.keys().next().copied(), forecasts.keys().last().copied()) else {
+        return Err(CalibrationError::NoContent(format!("no forecasts found in base data files in {}", base_data_dir)));
+    };
+
+    let actual = match config.calibration.pv_history.as_ref() {
+        Some(path) => load_history_csv(path)?,
+        None => fox_history(fox, FoxVariables::PvPower, first, last.add(TimeDelta::hours(1)))?,
+    };
+
+    let days = calibration_days(&forecasts, &actual);
+    if days.len() < MIN_CALIBRATION_DAYS {
+        return Err(CalibrationError::NoContent(format!(
+            "{} whole days with both forecast and actual production in {}, need at least {} (base data files are removed after 48 hours, set calibration.base_data_dir to an archive)",
+            days.len(), base_data_dir, MIN_CALIBRATION_DAYS)));
+    }
+    info!("Calibrating production parameters over {} days", days.len());
+
+    let residuals = |x: &[f64]| -> Result<Vec<f64>, CalibrationError> {
+        let params = with_parameters(&config.production, x);
+        let pv = PVProduction::new(&params, config.geo_ref.lat, config.geo_ref.long);
+        let cloud_factors = CloudFactors::from_production(&params);
+
+        let per_day = days.par_iter()
+            .map(|day| day_residuals(&pv, &cloud_factors, day))
+            .collect::<Result<Vec<Vec<f64>>, CalibrationError>>()?;
+
+        Ok(per_day.into_iter().flatten().collect())
+    };
+
+    let x0 = get_parameters(&config.production);
+    let lower: Vec<f64> = FITTED_PARAMETERS.iter().map(|p| p.1).collect();
+    let upper: Vec<f64> = FITTED_PARAMETERS.iter().map(|p| p.2).collect();
+    let actual_hours: Vec<f64> = days.iter().flat_map(|d| d.actual.iter().copied()).collect();
+
+    let before = fit_statistics(&residuals(&x0)?, &actual_hours);
+    let fit = bounded_least_squares(residuals, &x0, &lower, &upper, config.calibration.max_iterations)?;
+    let after = fit_statistics(&fit.residuals, &actual_hours);
+    info!("Calibration RMSE {:.3} kW -> {:.3} kW after {} iterations", before.rmse, after.rmse, fit.iterations);
+
+    let period = format!("{} - {}", days[0].start.with_timezone(&Local).date_naive(), days[days.len() - 1].start.with_timezone(&Local).date_naive());
+
+    let mut suggestion = format!("# Suggested production parameters, fitted {} over {} days ({})\n", Local::now().format("%Y-%m-%d %H:%M"), days.len(), period);
+    suggestion.push_str(&format!("# RMSE {:.3} kW -> {:.3} kW, see production_calibration_report.txt\n", before.rmse, after.rmse));
+    suggestion.push_str("[production]\n");
+    FITTED_PARAMETERS.iter().zip(fit.x.iter()).for_each(|((name, _, _), value)| {
+        suggestion.push_str(&format!("{:<19} = {:.3}\n", name, value));
+    });
+
+    let mut report = String::from("Production calibration report\n\n");
+    report.push_str(&format!("Created:      {}\n", Local::now().format("%Y-%m-%d %H:%M")));
+    report.push_str(&format!("Model:        {:?}\n", config.production.model));
+    report.push_str(&format!("Period:       {}\n", period));
+    report.push_str(&format!("Days:         {}\n", days.len()));
+    report.push_str(&format!("Hours:        {}\n", actual_hours.len()));
+    report.push_str(&format!("Iterations:   {} ({})\n\n", fit.iterations, if fit.converged { "converged" } else { "max iterations reached" }));
+    report.push_str(&format!("{:<22}{:>10}{:>10}\n", "Hourly error", "before", "after"));
+    report.push_str(&format!("{:<22}{:>10.3}{:>10.3}\n", "RMSE (kW)", before.rmse, after.rmse));
+    report.push_str(&format!("{:<22}{:>10.3}{:>10.3}\n", "MAE (kW)", before.mae, after.mae));
+    report.push_str(&format!("{:<22}{:>10.3}{:>10.3}\n", "Bias (kW)", before.bias, after.bias));
+    report.push_str(&format!("{:<22}{:>10.3}{:>10.3}\n\n", "R2", before.r2, after.r2));
+    report.push_str(&format!("{:<22}{:>10}{:>10}   bounds\n", "Parameter", "before", "after"));
+    for (j, (name, lower, upper)) in FITTED_PARAMETERS.iter().enumerate() {
+        let at_bound = if fit.x[j] <= *lower || fit.x[j] >= *upper { "   (at bound)" } else { "" };
+        report.push_str(&format!("{:<22}{:>10.3}{:>10.3}   [{}, {}]{}\n", name, x0[j], fit.x[j], lower, upper, at_bound));
+    }
+
+    fs::create_dir_all(output_dir)?;
+    fs::write(format!("{}production_calibration.toml", output_dir), suggestion)?;
+    fs::write(format!("{}production_calibration_report.txt", output_dir), report)?;
+    info!("Calibration result saved to {}", output_dir);
+
+    Ok(())
+}
+
+/// Returns the estimated minus actual hourly mean production (kW) for one day
+///
+/// # Arguments
+///
+/// * 'pv' - production model with the parameters to evaluate
+/// * 'cloud_factors' - cloud factors with the parameters to evaluate
+/// * 'day' - the day to evaluate
+fn day_residuals(pv: &PVProduction, cloud_factors: &CloudFactors, day: &CalibrationDay) -> Result<Vec<f64>, CalibrationError> {
+    let forecast = ForecastValues {
+        forecast: day.forecast.iter()
+            .cloned()
+            .map(|mut f| {
+                f.cloud_factor = cloud_factors.cloud_factor(f.lcc_mean as u8, f.mcc_mean as u8, f.hcc_mean as u8).3;
+                f
+            })
+            .collect(),
+    };
+
+    let power = pv.estimate(&forecast, day.start, day.end)?;
+
+    Ok(power.chunks(60)
+        .map(|c| c.iter().sum::<f64>() / c.len() as f64 / 1000.0)
+        .zip(day.actual.iter())
+        .map(|(estimated, actual)| estimated - actual)
+        .collect())
+}
+
+/// Returns the fitted parameters from production parameters, in the order of FITTED_PARAMETERS
+///
+/// # Arguments
+///
+/// * 'params' - production parameters
+fn get_parameters(params: &ProductionParameters) -> Vec<f64> {
+    vec![
+        params.tau,
+        params.k_gain,
+        params.iam_factor,
+        params.cloud_impact_factor,
+        params.low_clouds_factor,
+        params.mid_clouds_factor,
+        params.high_clouds_factor,
+    ]
+}
+
+/// Returns a copy of production parameters with the fitted parameters replaced
+///
+/// # Arguments
+///
+/// * 'params' - production parameters
+/// * 'x' - fitted parameters in the order of FITTED_PARAMETERS
+fn with_parameters(params: &ProductionParameters, x: &[f64]) -> ProductionParameters {
+    let mut params = params.clone();
+    params.tau = x[0];
+    params.k_gain = x[1];
+    params.iam_factor = x[2];
+    params.cloud_impact_factor = x[3];
+    params.low_clouds_factor = x[4];
+    params.mid_clouds_factor = x[5];
+    params.high_clouds_factor = x[6];
+
+    params
+}
+
+/// Returns the local days where every hour has both a forecast and actual production
+///
+/// # Arguments
+///
+/// * 'forecasts' - hourly forecasts
+/// * 'actual' - hourly mean actual production (kW)
+fn calibration_days(forecasts: &BTreeMap<DateTime<Utc>, ForecastValue>, actual: &HourlyValues) -> Vec<CalibrationDay> {
+    let mut dates = forecasts.keys().map(|t| t.with_timezone(&Local).date_naive()).collect::<Vec<_>>();
+    dates.dedup();
+
+    dates.into_iter()
+        .filter_map(|date| {
+            let start = date.and_hms_opt(0, 0, 0)?.and_local_timezone(Local).earliest()?.with_timezone(&Utc);
+            let end = date.succ_opt()?.and_hms_opt(0, 0, 0)?.and_local_timezone(Local).earliest()?.with_timezone(&Utc);
+
+            let mut forecast: Vec<ForecastValue> = Vec::new();
+            let mut day_actual: Vec<f64> = Vec::new();
+            let mut hour = start;
+            while hour < end {
+                forecast.push(forecasts.get(&hour)?.clone());
+                day_actual.push(*actual.get(&hour)?);
+                hour = hour.add(TimeDelta::hours(1));
+            }
+            if let Some(f) = forecasts.get(&end) {
+                forecast.push(f.clone());
+            }
+
+            Some(CalibrationDay { start, end, forecast, actual: day_actual })
+        })
+        .collect()
+}
+
+/// Returns error statistics for residuals (estimated - actual) given the actual values
+///
+/// # Arguments
+///
+/// * 'residuals' - estimated minus actual values
+/// * 'actual' - actual values
+fn fit_statistics(residuals: &[f64], actual: &[f64]) -> FitStatistics {
+    let n = residuals.len().max(1) as f64;
+    let sse = residuals.iter().map(|r| r * r).sum::<f64>();
+    let mean = actual.iter().sum::<f64>() / n;
+    let sst = actual.iter().map(|a| (a - mean).powi(2)).sum::<f64>();
+
+    FitStatistics {
+        rmse: (sse / n).sqrt(),
+        mae: residuals.iter().map(|r| r.abs()).sum::<f64>() / n,
+        bias: residuals.iter().sum::<f64>() / n,
+        r2: if sst > 0.0 { 1.0 - sse / sst } else { 0.0 },
+    }
+}
+
+/// Error depicting errors that occur while calibrating production parameters
+///
+#[derive(Debug, Error)]
+pub enum CalibrationError {
+    #[error("FileError: {0}")]
+    File(String),
+    #[error("IoError: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("DocumentError: {0}")]
+    Document(#[from] serde_json::Error),
+    #[error("ParseError: {0}")]
+    Parse(String),
+    #[error("FoxError: {0}")]
+    Fox(#[from] foxess::FoxError),
+    #[error("ProductionError: {0}")]
+    Production(#[from] ProductionError),
+    #[error("LeastSquaresError: {0}")]
+    LeastSquares(#[from] LeastSquaresError),
+    #[error("ConfigError: {0}")]
+    Config(#[from] LoadConfigurationError),
+    #[error("SplineError: {0}")]
+    Spline(#[from] SplineError),
+    #[error("NoContentError: {0}")]
+    NoContent(String),
+}
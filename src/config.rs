@@ -1,9 +1,13 @@
+use std::collections::HashMap;
 use std::fs;
 use log::LevelFilter;
 use serde::Deserialize;
 use anyhow::Result;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, NaiveDate};
 use thiserror::Error;
+use crate::calendar::all_day_events;
+use crate::consumption::{ConsumptionDiagram, DayOverride, SeasonalDiagram, WEEKDAYS};
+use crate::spline::MonotonicCubicSpline;
 
 #[derive(Deserialize)]
 pub struct GeoRef {
@@ -16,27 +20,114 @@ pub struct ConsumptionParameters {
     pub min_avg_load: f64,
     pub max_avg_load: f64,
     pub curve: Vec<(f64, f64)>,
+    #[serde(default)]
+    pub calendar: CalendarParameters,
     #[serde(skip)]
-    pub diagram: Option<[[f64;24];7]>,
+    pub diagram: Option<ConsumptionDiagram>,
 }
 
-#[derive(Deserialize)]
+/// Calendar overrides of the consumption diagram, see consumption
+#[derive(Deserialize, Clone)]
+pub struct CalendarParameters {
+    #[serde(default = "default_swedish_holidays")]
+    pub swedish_holidays: bool,
+    #[serde(default = "default_holiday_profile")]
+    pub holiday_profile: String,
+    pub override_file: Option<String>,
+    pub ics_file: Option<String>,
+    #[serde(default = "default_ics_profile")]
+    pub ics_profile: String,
+}
+
+impl Default for CalendarParameters {
+    fn default() -> Self {
+        Self {
+            swedish_holidays: default_swedish_holidays(),
+            holiday_profile: default_holiday_profile(),
+            override_file: None,
+            ics_file: None,
+            ics_profile: default_ics_profile(),
+        }
+    }
+}
+
+fn default_swedish_holidays() -> bool {
+    true
+}
+
+fn default_holiday_profile() -> String {
+    "sunday".to_string()
+}
+
+fn default_ics_profile() -> String {
+    "away".to_string()
+}
+
+#[derive(Deserialize, Clone)]
 pub struct ProductionParameters {
-    pub panel_power: f64,
-    pub panel_slope: f64,
-    pub panel_east_azm: f64,
+    #[serde(default)]
+    pub model: ProductionModel,
+    #[serde(default = "default_albedo")]
+    pub albedo: f64,
+    #[serde(default)]
+    pub arrays: Vec<PVArray>,
+    pub inverter: Option<InverterParameters>,
     pub panel_temp_red: f64,
     pub tau: f64,
     pub tau_down: f64,
     pub k_gain: f64,
     pub iam_factor: f64,
-    pub start_azm_elv: Vec<(f64, f64)>,
-    pub stop_azm_elv: Vec<(f64, f64)>,
     pub cloud_impact_factor: f64,
     pub low_clouds_factor: f64,
     pub mid_clouds_factor: f64,
     pub high_clouds_factor: f64,
+
+    // East/west roof parameters from before arrays were introduced, mapped into arrays on load
+    pub panel_power: Option<f64>,
+    pub panel_slope: Option<f64>,
+    pub panel_east_azm: Option<f64>,
+    #[serde(default)]
+    pub start_azm_elv: Vec<(f64, f64)>,
+    #[serde(default)]
+    pub stop_azm_elv: Vec<(f64, f64)>,
 }
+
+/// One PV array, i.e. a group of equal panels on one plane
+#[derive(Deserialize, Clone, Debug)]
+pub struct PVArray {
+    pub azimuth: f64,
+    pub tilt: f64,
+    pub panels: u32,
+    pub panel_power: f64,
+    #[serde(default)]
+    pub start_azm_elv: Vec<(f64, f64)>,
+    #[serde(default)]
+    pub stop_azm_elv: Vec<(f64, f64)>,
+    #[serde(default)]
+    pub horizon: Vec<(f64, f64)>,
+    pub horizon_file: Option<String>,
+}
+
+/// Inverter limits and conversion losses applied to the PV arrays' DC power
+#[derive(Deserialize, Clone, Debug)]
+pub struct InverterParameters {
+    pub ac_max_power: f64,
+    pub efficiency_curve: Vec<(f64, f64)>,
+    pub export_limit: Option<f64>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductionModel {
+    #[default]
+    ClearSky,
+    Irradiance,
+}
+
+fn default_albedo() -> f64 {
+    0.2
+}
+
 #[derive(Deserialize)]
 pub struct ChargeParameters {
     pub bat_capacity_kwh: f64,
@@ -45,6 +136,35 @@ pub struct ChargeParameters {
     pub charge_kwh_hour: f64,
     pub charge_efficiency: f64,
     pub discharge_efficiency: f64,
+    #[serde(default)]
+    pub max_export_kw: f64,
+    pub max_import_kw: Option<f64>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PowerFee {
+    pub sek_kw: f64,
+    pub peak_hours: Vec<(u32, u32)>,
+    pub peaks_averaged: usize,
+    #[serde(default)]
+    pub months: Vec<u32>,
+    #[serde(default)]
+    pub weekdays_only: bool,
+}
+
+/// A time band where another variable grid fee than the default applies. Empty lists match all
+/// months, weekdays (1 = Monday to 7 = Sunday) and hours respectively.
+#[derive(Deserialize, Clone, Debug)]
+pub struct VariableFeeRule {
+    pub fee: f64,
+    #[serde(default)]
+    pub months: Vec<u32>,
+    #[serde(default)]
+    pub weekdays: Vec<u32>,
+    #[serde(default)]
+    pub hours: Vec<(u32, u32)>,
+    #[serde(default)]
+    pub exclude_holidays: bool,
 }
 
 #[derive(Deserialize)]
@@ -58,12 +178,74 @@ pub struct TariffFees {
     pub guarantees_of_origin: f64,
     pub fixed: f64,
     pub production_price: f64,
+    pub power_fee: Option<PowerFee>,
+    #[serde(default)]
+    pub variable_fee_rules: Vec<VariableFeeRule>,
+}
+
+/// Declarative formula for how buy and sell prices are built from the spot price
+#[derive(Deserialize, Clone, Debug)]
+pub struct TariffFormula {
+    pub buy_vat_percentage: f64,
+    pub sell_vat_percentage: f64,
+    pub components: Vec<TariffComponent>,
+}
+
+/// One component of the tariff formula, `value` is in öre/kWh for fixed components and in
+/// percent for percentage components. The variable fee component takes its value from the
+/// time-of-use rules in `[tariff_fees]`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct TariffComponent {
+    pub kind: ComponentKind,
+    #[serde(default)]
+    pub value: f64,
+    pub applies_to: TariffSide,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentKind {
+    Fixed,
+    SpotPercentage,
+    DayAveragePercentage,
+    VariableFee,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TariffSide {
+    Buy,
+    Sell,
+    Both,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Optimizer {
+    #[default]
+    Search,
+    DynamicProgramming,
+}
+
+/// How to value energy left in the battery at the end of the schedule (SEK/kWh delivered)
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case", tag = "method")]
+pub enum TerminalValue {
+    #[default]
+    None,
+    Fixed { sek_kwh: f64 },
+    NextDayPrice { fraction: f64, fallback_sek_kwh: f64 },
+    Forecast { fraction: f64, fallback_sek_kwh: f64 },
 }
 
 #[derive(Deserialize)]
 pub struct Scheduler {
     pub min_saving: f64,
     pub mode_scheduler: bool,
+    #[serde(default)]
+    pub optimizer: Optimizer,
+    #[serde(default)]
+    pub terminal_value: TerminalValue,
 }
 
 #[derive(Deserialize)]
@@ -74,10 +256,88 @@ pub struct FoxESS {
     pub inverter_sn: String,
 }
 
+#[derive(Deserialize)]
+pub struct NordPoolParameters {
+    pub area: String,
+    pub currency: String,
+}
+
+impl Default for NordPoolParameters {
+    fn default() -> Self {
+        Self {
+            area: "SE4".to_string(),
+            currency: "SEK".to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceProvider {
+    NordPool,
+    Entsoe,
+    File,
+}
+
+#[derive(Deserialize)]
+pub struct PriceParameters {
+    #[serde(default = "default_price_providers")]
+    pub providers: Vec<PriceProvider>,
+    pub cache_dir: Option<String>,
+    pub file: Option<String>,
+    pub forecast: Option<PriceForecastParameters>,
+}
+
+/// Parameters for estimating spot prices beyond the published day ahead prices
+#[derive(Deserialize, Clone)]
+pub struct PriceForecastParameters {
+    pub hours: i64,
+    pub history_weeks: i64,
+    pub history_weight: f64,
+    pub temperature_coefficient: f64,
+}
+
+impl Default for PriceParameters {
+    fn default() -> Self {
+        Self {
+            providers: default_price_providers(),
+            cache_dir: None,
+            file: None,
+            forecast: None,
+        }
+    }
+}
+
+fn default_price_providers() -> Vec<PriceProvider> {
+    vec![PriceProvider::NordPool]
+}
+
+#[derive(Deserialize)]
+pub struct EntsoeParameters {
+    #[serde(default)]
+    pub api_token: String,
+    pub domain: String,
+    pub exchange_rate: f64,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ForecastSource {
+    #[default]
+    Mygrid,
+    Smhi,
+    OpenMeteo,
+}
+
 #[derive(Deserialize)]
 pub struct Forecast {
+    #[serde(default)]
+    pub provider: ForecastSource,
+    #[serde(default)]
     pub host: String,
+    #[serde(default)]
     pub port: u16,
+    pub url: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -91,6 +351,54 @@ pub struct MailParameters {
     pub to: String,
 }
 
+/// Parameters for calibrating production parameters, see calibration
+#[derive(Deserialize)]
+pub struct CalibrationParameters {
+    pub base_data_dir: Option<String>,
+    pub pv_history: Option<String>,
+    pub consumption_history: Option<String>,
+    pub temperature_history: Option<String>,
+    pub output_dir: Option<String>,
+    #[serde(default = "default_calibration_iterations")]
+    pub max_iterations: usize,
+    #[serde(default)]
+    pub curve_resolution: CurveResolution,
+    #[serde(default = "default_curve_points")]
+    pub curve_points: usize,
+}
+
+impl Default for CalibrationParameters {
+    fn default() -> Self {
+        Self {
+            base_data_dir: None,
+            pv_history: None,
+            consumption_history: None,
+            temperature_history: None,
+            output_dir: None,
+            max_iterations: default_calibration_iterations(),
+            curve_resolution: CurveResolution::default(),
+            curve_points: default_curve_points(),
+        }
+    }
+}
+
+fn default_calibration_iterations() -> usize {
+    50
+}
+
+fn default_curve_points() -> usize {
+    6
+}
+
+/// Resolution of the consumption and temperature samples the consumption curve is fitted to
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CurveResolution {
+    #[default]
+    Daily,
+    Hourly,
+}
+
 #[derive(Deserialize)]
 pub struct Files {
     pub schedule_dir: String,
@@ -115,9 +423,17 @@ pub struct Config {
     pub production: ProductionParameters,
     pub charge: ChargeParameters,
     pub tariff_fees: TariffFees,
+    pub tariff_formula: Option<TariffFormula>,
     pub scheduler: Scheduler,
     pub fox_ess: FoxESS,
+    #[serde(default)]
+    pub prices: PriceParameters,
+    #[serde(default)]
+    pub nordpool: NordPoolParameters,
+    pub entsoe: Option<EntsoeParameters>,
     pub forecast: Forecast,   
+    #[serde(default)]
+    pub calibration: CalibrationParameters,
     pub mail: MailParameters,
     pub files: Files,
     pub general: General,
@@ -125,18 +441,48 @@ pub struct Config {
 
 #[derive(Deserialize)]
 struct DaysDiagram {
-    monday: [f64; 24],
-    tuesday: [f64; 24],
-    wednesday: [f64; 24],
-    thursday: [f64; 24],
-    friday: [f64; 24],
-    saturday: [f64; 24],
-    sunday: [f64; 24],
+    monday: Vec<f64>,
+    tuesday: Vec<f64>,
+    wednesday: Vec<f64>,
+    thursday: Vec<f64>,
+    friday: Vec<f64>,
+    saturday: Vec<f64>,
+    sunday: Vec<f64>,
+}
+
+#[derive(Deserialize)]
+struct SeasonalDaysDiagram {
+    name: String,
+    from: String,
+    to: String,
+    consumption_diagram: DaysDiagram,
+}
+
+#[derive(Deserialize)]
+struct HorizonProfile {
+    horizon: Vec<(f64, f64)>,
 }
 
 #[derive(Deserialize)]
 struct HouseHoldConsumption {
-    consumption_diagram: DaysDiagram
+    consumption_diagram: DaysDiagram,
+    #[serde(default)]
+    seasonal_diagram: Vec<SeasonalDaysDiagram>,
+    #[serde(default)]
+    day_profiles: HashMap<String, Vec<f64>>,
+}
+
+#[derive(Deserialize)]
+struct CalendarOverrides {
+    #[serde(rename = "override", default)]
+    overrides: Vec<CalendarOverride>,
+}
+
+#[derive(Deserialize)]
+struct CalendarOverride {
+    from: NaiveDate,
+    to: NaiveDate,
+    profile: String,
 }
 
 /// Loads the configuration file and returns a struct with all configuration items
@@ -151,34 +497,284 @@ pub fn load_config(config_path: &str) -> Result<Config, LoadConfigurationError>
     let mut config: Config = toml::from_str(&toml)
         .map_err(|e| LoadConfigurationError::TomlParsingError(format!("config file: {}", e.to_string())))?;
 
-    let cons_diagram = load_consumption_diagram(&config.files.cons_diagram)?;
+    check_consumption_curve(&config.consumption.curve)?;
+    map_legacy_arrays(&mut config.production)?;
+    if let Some(inverter) = config.production.inverter.as_ref() {
+        check_efficiency_curve(&inverter.efficiency_curve)?;
+    }
+    let mut cons_diagram = load_consumption_diagram(&config.files.cons_diagram)?;
+    load_calendar(&mut cons_diagram, &config.consumption.calendar)?;
     config.consumption.diagram = Some(cons_diagram);
+
+    for array in config.production.arrays.iter_mut() {
+        if let Some(horizon_file) = array.horizon_file.as_ref() {
+            array.horizon = load_horizon(horizon_file)?;
+        }
+        array.horizon.iter_mut().for_each(|p| p.0 = p.0.rem_euclid(360.0));
+        array.horizon.sort_by(|a, b| a.0.total_cmp(&b.0));
+    }
     
     Ok(config)
 }
 
-/// Loads consumption diagram configuration
+/// Maps the east/west roof parameters used before PV arrays were introduced into two arrays,
+/// 12 panels facing panel_east_azm and 15 panels facing the opposite direction, both with the
+/// same obstacle windows, as the roof was modelled then. Arrays given in the configuration take
+/// precedence over the old parameters.
+///
+/// # Arguments
+///
+/// * 'production' - production parameters to add the arrays to
+fn map_legacy_arrays(production: &mut ProductionParameters) -> Result<(), LoadConfigurationError> {
+    if !production.arrays.is_empty() {
+        return Ok(());
+    }
+
+    let (Some(panel_power), Some(tilt), Some(east_azimuth)) = (production.panel_power, production.panel_slope, production.panel_east_azm) else {
+        return Err(LoadConfigurationError::ConfigurationFileError(
+            "config file: no [[production.arrays]] given, move panel_power, panel_slope, panel_east_azm and the obstacle windows into one array per roof side".to_string()));
+    };
+
+    let array = |azimuth: f64, panels: u32| PVArray {
+        azimuth,
+        tilt,
+        panels,
+        panel_power,
+        start_azm_elv: production.start_azm_elv.clone(),
+        stop_azm_elv: production.stop_azm_elv.clone(),
+        horizon: Vec::new(),
+        horizon_file: None,
+    };
+    production.arrays = vec![array(east_azimuth, 12), array(180.0 + east_azimuth, 15)];
+
+    Ok(())
+}
+
+/// Checks that a consumption curve follows the rules for temperature and index pairs, i.e.
+/// strictly increasing temperatures, a non-increasing index within 0.0 to 1.0, index 1.0 on the
+/// lowest temperature and index 0.0 on the highest temperature
+///
+/// # Arguments
+///
+/// * 'curve' - consumption curve as temperature and index pairs
+pub fn check_consumption_curve(curve: &[(f64, f64)]) -> Result<(), LoadConfigurationError> {
+    let (Some(first), Some(last)) = (curve.first(), curve.last()) else {
+        return Err(LoadConfigurationError::CurveError("consumption curve is empty".to_string()));
+    };
+    if curve.len() < 2 {
+        return Err(LoadConfigurationError::CurveError("consumption curve needs at least two points".to_string()));
+    }
+    if first.1 != 1.0 || last.1 != 0.0 {
+        return Err(LoadConfigurationError::CurveError("consumption curve index must be 1.0 on the lowest and 0.0 on the highest temperature".to_string()));
+    }
+    for pair in curve.windows(2) {
+        if pair[1].0 <= pair[0].0 {
+            return Err(LoadConfigurationError::CurveError(format!("consumption curve temperatures must be strictly increasing, {} follows {}", pair[1].0, pair[0].0)));
+        }
+        if pair[1].1 > pair[0].1 || !(0.0..=1.0).contains(&pair[1].1) {
+            return Err(LoadConfigurationError::CurveError(format!("consumption curve index must decrease within 0.0 to 1.0, {} follows {}", pair[1].1, pair[0].1)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that an inverter efficiency curve follows the rules for load fraction and efficiency
+/// pairs, i.e. strictly increasing load fractions and efficiencies within 0.0 to 1.0
+///
+/// # Arguments
+///
+/// * 'curve' - efficiency curve as load fraction and efficiency pairs
+fn check_efficiency_curve(curve: &[(f64, f64)]) -> Result<(), LoadConfigurationError> {
+    if curve.len() < 2 {
+        return Err(LoadConfigurationError::CurveError("inverter efficiency curve needs at least two points".to_string()));
+    }
+    if let Some(point) = curve.iter().find(|p| !(0.0..=1.0).contains(&p.1)) {
+        return Err(LoadConfigurationError::CurveError(format!("inverter efficiency must be within 0.0 to 1.0, got {} at load {}", point.1, point.0)));
+    }
+
+    let (x, y): (Vec<f64>, Vec<f64>) = curve.iter().copied().unzip();
+    MonotonicCubicSpline::new(&x, &y)
+        .map_err(|e| LoadConfigurationError::CurveError(format!("inverter efficiency curve: {}", e)))?;
+
+    Ok(())
+}
+
+/// Loads a horizon profile as azimuth (degrees from north, eastward) and elevation pairs.
+/// A file ending with .toml is expected to hold a list as in 'horizon = [[azimuth, elevation]...]',
+/// any other file is read as a PVGIS horizon CSV, where azimuth is given from south (negative east)
+///
+/// # Arguments
+///
+/// * 'horizon_path' - path to the horizon file
+fn load_horizon(horizon_path: &str) -> Result<Vec<(f64, f64)>, LoadConfigurationError> {
+    let content = fs::read_to_string(horizon_path)
+        .map_err(|e| LoadConfigurationError::ConfigurationFileError(format!("horizon file: {}", e)))?;
+
+    let horizon: Vec<(f64, f64)> = if horizon_path.ends_with(".toml") {
+        toml::from_str::<HorizonProfile>(&content)
+            .map_err(|e| LoadConfigurationError::TomlParsingError(format!("horizon file: {}", e)))?
+            .horizon
+    } else {
+        // Data lines start with azimuth and horizon elevation, header and footer lines are skipped
+        content.lines()
+            .filter_map(|line| {
+                let mut fields = line.split(['\t', ',', ';', ' ']).filter(|f| !f.is_empty());
+                let azimuth = fields.next()?.parse::<f64>().ok()?;
+                let elevation = fields.next()?.parse::<f64>().ok()?;
+                Some((azimuth + 180.0, elevation))
+            })
+            .collect()
+    };
+
+    if horizon.is_empty() {
+        return Err(LoadConfigurationError::ConfigurationFileError(format!("horizon file: no horizon points in {}", horizon_path)));
+    }
+
+    Ok(horizon)
+}
+
+/// Loads consumption diagram configuration. Days are given either as 24 hourly values or as 96
+/// quarter hour values, and hourly values are spread over the quarters of each hour. Seasonal
+/// variants are given with a name and a date range as month and day, e.g. from "05-15" to "08-31".
 ///
 /// # Arguments
 ///
 /// * 'diagram_path' - path to the consumption diagram file
-fn load_consumption_diagram(diagram_path: &str) -> Result<[[f64;24];7], LoadConfigurationError> {
+fn load_consumption_diagram(diagram_path: &str) -> Result<ConsumptionDiagram, LoadConfigurationError> {
     
     let toml = fs::read_to_string(diagram_path)
         .map_err(|e| LoadConfigurationError::ConfigurationFileError(format!("consumption diagram: {}", e.to_string())))?;
     let hhc: HouseHoldConsumption = toml::from_str(&toml)
         .map_err(|e| LoadConfigurationError::TomlParsingError(format!("consumption diagram: {}", e.to_string())))?;
 
-    let days: [[f64;24];7] = [
-        hhc.consumption_diagram.monday,
-        hhc.consumption_diagram.tuesday,
-        hhc.consumption_diagram.wednesday,
-        hhc.consumption_diagram.thursday,
-        hhc.consumption_diagram.friday,
-        hhc.consumption_diagram.saturday,
-        hhc.consumption_diagram.sunday];
+    let mut profiles: HashMap<String, [f64;96]> = HashMap::new();
+    for (name, values) in hhc.day_profiles.iter() {
+        if WEEKDAYS.contains(&name.as_str()) {
+            return Err(LoadConfigurationError::ConfigurationFileError(format!("consumption diagram: day profile {} can't be named as a weekday", name)));
+        }
+        profiles.insert(name.clone(), day_quarters(values, name)?);
+    }
+
+    let mut seasons: Vec<SeasonalDiagram> = Vec::new();
+    for season in hhc.seasonal_diagram.iter() {
+        seasons.push(SeasonalDiagram {
+            name: season.name.clone(),
+            from: month_day(&season.from)?,
+            to: month_day(&season.to)?,
+            days: week_quarters(&season.consumption_diagram, &season.name)?,
+        });
+    }
+
+    Ok(ConsumptionDiagram {
+        days: week_quarters(&hhc.consumption_diagram, "consumption_diagram")?,
+        seasons,
+        profiles,
+        overrides: Vec::new(),
+        holiday_profile: None,
+    })
+}
+
+/// Loads calendar overrides of the consumption diagram from an override file and from all-day
+/// events in an ICS file, and sets the day profile for Swedish holidays if enabled. Overrides from the override file
+/// go before those from the ICS file. An ICS event uses the day profile named by its summary if
+/// there is one, and otherwise the configured ICS profile.
+///
+/// # Arguments
+///
+/// * 'diagram' - consumption diagram to add overrides to
+/// * 'calendar' - calendar parameters
+fn load_calendar(diagram: &mut ConsumptionDiagram, calendar: &CalendarParameters) -> Result<(), LoadConfigurationError> {
+    let is_profile = |name: &str| WEEKDAYS.contains(&name) || diagram.profiles.contains_key(name);
+    let mut overrides: Vec<DayOverride> = Vec::new();
+
+    if let Some(override_file) = calendar.override_file.as_ref() {
+        let toml = fs::read_to_string(override_file)
+            .map_err(|e| LoadConfigurationError::ConfigurationFileError(format!("calendar override file: {}", e)))?;
+        let calendar_overrides: CalendarOverrides = toml::from_str(&toml)
+            .map_err(|e| LoadConfigurationError::TomlParsingError(format!("calendar override file: {}", e)))?;
+
+        for o in calendar_overrides.overrides {
+            if o.to < o.from {
+                return Err(LoadConfigurationError::ConfigurationFileError(format!("calendar override file: {} is before {}", o.to, o.from)));
+            }
+            overrides.push(DayOverride { from: o.from, to: o.to, profile: o.profile });
+        }
+    }
+
+    if let Some(ics_file) = calendar.ics_file.as_ref() {
+        let ics = fs::read_to_string(ics_file)
+            .map_err(|e| LoadConfigurationError::ConfigurationFileError(format!("ics file: {}", e)))?;
+
+        for event in all_day_events(&ics) {
+            let summary = event.summary.trim().to_lowercase();
+            let profile = if is_profile(&summary) { summary } else { calendar.ics_profile.clone() };
+            overrides.push(DayOverride { from: event.first, to: event.last, profile });
+        }
+    }
+
+    let holiday_profile = calendar.swedish_holidays.then(|| calendar.holiday_profile.clone());
+    for profile in overrides.iter().map(|o| &o.profile).chain(holiday_profile.iter()) {
+        if !is_profile(profile) {
+            return Err(LoadConfigurationError::ConfigurationFileError(format!("calendar: unknown day profile {}, expected a weekday or a day profile in the consumption diagram", profile)));
+        }
+    }
+
+    diagram.overrides = overrides;
+    diagram.holiday_profile = holiday_profile;
+
+    Ok(())
+}
+
+/// Returns the days of a diagram as quarter hour values, monday first
+///
+/// # Arguments
+///
+/// * 'diagram' - days as given in the consumption diagram file
+/// * 'name' - name of the diagram, used in error messages
+fn week_quarters(diagram: &DaysDiagram, name: &str) -> Result<[[f64;96];7], LoadConfigurationError> {
+    let days = [&diagram.monday, &diagram.tuesday, &diagram.wednesday, &diagram.thursday,
+        &diagram.friday, &diagram.saturday, &diagram.sunday];
+
+    let mut quarters = [[0.0;96];7];
+    for (d, values) in days.iter().enumerate() {
+        quarters[d] = day_quarters(values, &format!("{} {}", name, WEEKDAYS[d]))?;
+    }
+
+    Ok(quarters)
+}
+
+/// Returns a day given as 24 hourly or 96 quarter hour values as quarter hour values, where
+/// hourly values are spread over the quarters of each hour
+///
+/// # Arguments
+///
+/// * 'values' - hourly or quarter hour values
+/// * 'name' - name of the day, used in error messages
+fn day_quarters(values: &[f64], name: &str) -> Result<[f64;96], LoadConfigurationError> {
+    let mut quarters = [0.0;96];
+    match values.len() {
+        24 => quarters.iter_mut().enumerate().for_each(|(q, v)| *v = values[q / 4]),
+        96 => quarters.copy_from_slice(values),
+        n => return Err(LoadConfigurationError::ConfigurationFileError(
+            format!("consumption diagram: {} has {} values, expected 24 or 96", name, n))),
+    }
+
+    Ok(quarters)
+}
+
+/// Parses a month and day given as "MM-DD"
+///
+/// # Arguments
+///
+/// * 'month_day' - month and day, e.g. "05-15"
+fn month_day(month_day: &str) -> Result<(u32, u32), LoadConfigurationError> {
+    let parsed = month_day.split_once('-')
+        .and_then(|(m, d)| Some((m.trim().parse::<u32>().ok()?, d.trim().parse::<u32>().ok()?)))
+        .filter(|(m, d)| NaiveDate::from_ymd_opt(2000, *m, *d).is_some());
 
-        Ok(days)
+    parsed.ok_or(LoadConfigurationError::ConfigurationFileError(
+        format!("consumption diagram: invalid month and day '{}', expected MM-DD", month_day)))
 }
 
 /// Error depicting errors that occur while loading the configuration file
@@ -189,4 +785,70 @@ pub enum LoadConfigurationError {
     ConfigurationFileError(String),
     #[error("TomlParsingError: {0}")]
     TomlParsingError(String),
+    #[error("CurveError: {0}")]
+    CurveError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY_PRODUCTION: &str = r#"
+        panel_power = 500.0
+        panel_slope = 40.0
+        panel_east_azm = -82.5
+        panel_temp_red = 0.4
+        tau = 3.0
+        tau_down = 4.0
+        k_gain = 70.0
+        iam_factor = 0.8
+        start_azm_elv = [[0.0,12.0],[90.0,6.5],[106.0,15.0],[133.0,8.0]]
+        stop_azm_elv = [[180.0,5.0]]
+        cloud_impact_factor = 0.75
+        low_clouds_factor = 1.0
+        mid_clouds_factor = 0.5
+        high_clouds_factor = 0.25
+    "#;
+
+    #[test]
+    fn maps_legacy_roof_parameters_into_arrays() {
+        let mut production: ProductionParameters = toml::from_str(LEGACY_PRODUCTION).unwrap();
+
+        map_legacy_arrays(&mut production).unwrap();
+
+        let arrays = &production.arrays;
+        assert_eq!(arrays.len(), 2);
+        assert_eq!((arrays[0].azimuth, arrays[0].tilt, arrays[0].panels, arrays[0].panel_power), (-82.5, 40.0, 12, 500.0));
+        assert_eq!((arrays[1].azimuth, arrays[1].tilt, arrays[1].panels, arrays[1].panel_power), (97.5, 40.0, 15, 500.0));
+        assert!(arrays.iter().all(|a| a.start_azm_elv.len() == 4 && a.stop_azm_elv == vec![(180.0, 5.0)]));
+    }
+
+    #[test]
+    fn requires_arrays_or_legacy_roof_parameters() {
+        let mut production: ProductionParameters = toml::from_str(&LEGACY_PRODUCTION.replace("panel_slope = 40.0", "")).unwrap();
+
+        let result = map_legacy_arrays(&mut production);
+
+        assert!(matches!(result, Err(LoadConfigurationError::ConfigurationFileError(e)) if e.contains("[[production.arrays]]")));
+    }
+
+    #[test]
+    fn example_config_arrays_take_precedence() {
+        let mut config: Config = toml::from_str(include_str!("../config/config.toml")).unwrap();
+
+        map_legacy_arrays(&mut config.production).unwrap();
+
+        assert_eq!(config.production.arrays.iter().map(|a| a.panels).collect::<Vec<u32>>(), vec![12, 15]);
+    }
+
+    #[test]
+    fn converts_pvgis_azimuth_from_south_to_north() {
+        let horizon = load_horizon(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/pvgis_horizon.csv")).unwrap();
+
+        assert_eq!(horizon.len(), 48);
+        assert_eq!(horizon[0], (0.0, 1.0));     // North
+        assert_eq!(horizon[12], (90.0, 12.5));  // East
+        assert_eq!(horizon[24], (180.0, 5.0));  // South
+        assert_eq!(horizon[47], (352.5, 0.6));
+    }
 }
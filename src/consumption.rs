@@ -1,5 +1,7 @@
+use std::collections::HashMap;
 use std::ops::Add;
-use chrono::{Datelike, TimeDelta, Timelike};
+use chrono::{Datelike, NaiveDate, TimeDelta, Timelike};
+use crate::calendar::is_swedish_holiday;
 use crate::config::ConsumptionParameters;
 use crate::models::ForecastValues;
 use crate::spline::MonotonicCubicSpline;
@@ -10,7 +12,7 @@ use crate::spline::MonotonicCubicSpline;
 pub struct Consumption {
     min_avg_load: f64,
     max_avg_load: f64,
-    diagram: [[f64;24];7],
+    diagram: ConsumptionDiagram,
     curve_x_min: f64,
     curve_x_max: f64,
     curve: MonotonicCubicSpline,
@@ -31,7 +33,7 @@ impl Consumption {
         Consumption { 
             min_avg_load: config.min_avg_load,
             max_avg_load: config.max_avg_load,
-            diagram: config.diagram.unwrap(),
+            diagram: config.diagram.clone().unwrap(),
             curve_x_min: curve_x[0],
             curve_x_max: curve_x[curve_x.len() - 1],
             curve: MonotonicCubicSpline::new(&curve_x, &curve_y)
@@ -39,8 +41,8 @@ impl Consumption {
         }
     }
     
-    /// Calculates hourly household consumption based on the temperature forecast and
-    /// returns it as minute values in an array.
+    /// Calculates household consumption per quarter hour based on the temperature forecast and
+    /// the consumption diagram valid for each day, and returns it as minute values in an array.
     ///
     /// Since all datetime values are to be in Utc, we need the current offset to compensate
     /// for the household diagram being in local time (it is given from how people act during
@@ -57,13 +59,13 @@ impl Consumption {
 
         for v in forecast.forecast.iter() {
             let valid_time = v.valid_time.add(TimeDelta::seconds(local_offset));
-            let week_day = valid_time.weekday().num_days_from_monday() as usize;
+            let day = self.diagram.day(valid_time.date_naive());
             let hour = valid_time.hour() as usize;
-            let power_per_hour = self.consumption_curve(v.temp) + self.diagram[week_day][hour];
-            for i in minute_index..minute_index + 60 {
-                p[i] = power_per_hour;
+            let power_by_temp = self.consumption_curve(v.temp);
+            for quarter in day[hour * 4..hour * 4 + 4].iter() {
+                p[minute_index..minute_index + 15].fill(power_by_temp + quarter);
+                minute_index += 15;
             }
-            minute_index += 60;
         }
 
         p
@@ -79,7 +81,7 @@ impl Consumption {
     /// # Arguments
     ///
     /// * 'temp' - outside temperature
-    fn consumption_curve(&self, temp: f64) -> f64 {
+    pub fn consumption_curve(&self, temp: f64) -> f64 {
         let capped_temp = temp.max(self.curve_x_min).min(self.curve_x_max);
         let curve = self.curve.interpolate(capped_temp).clamp(0.0, 1.0);
 
@@ -87,4 +89,95 @@ impl Consumption {
     }
 }
 
+/// Weekday names as used for days in the consumption diagram and as day profiles, monday first
+pub const WEEKDAYS: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
 
+/// Household consumption per weekday (monday first) and local quarter hour, on top of the
+/// temperature dependent consumption. Seasonal variants replace it within their date ranges,
+/// and calendar overrides and holidays replace single days with a day profile.
+///
+#[derive(Clone)]
+pub struct ConsumptionDiagram {
+    pub days: [[f64;96];7],
+    pub seasons: Vec<SeasonalDiagram>,
+    pub profiles: HashMap<String, [f64;96]>,
+    pub overrides: Vec<DayOverride>,
+    pub holiday_profile: Option<String>,
+}
+
+/// Day profile to use instead of the weekday from and to a date, both inclusive. The profile is
+/// either a weekday name, using that weekday of the diagram valid for the date, or the name of a
+/// day profile given in the consumption diagram file, e.g. "away".
+///
+#[derive(Clone)]
+pub struct DayOverride {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub profile: String,
+}
+
+/// Named variant of the consumption diagram valid from and to a month and day, both inclusive.
+/// A range where from is after to wraps over the new year.
+///
+#[derive(Clone)]
+pub struct SeasonalDiagram {
+    pub name: String,
+    pub from: (u32, u32),
+    pub to: (u32, u32),
+    pub days: [[f64;96];7],
+}
+
+impl ConsumptionDiagram {
+    /// Returns the index of the seasonal variant valid for a date, or None if no season covers
+    /// it. If seasons overlap, the first one given wins.
+    ///
+    /// # Arguments
+    ///
+    /// * 'date' - local date
+    pub fn season(&self, date: NaiveDate) -> Option<usize> {
+        let month_day = (date.month(), date.day());
+        self.seasons.iter().position(|s| {
+            if s.from <= s.to {
+                s.from <= month_day && month_day <= s.to
+            } else {
+                month_day >= s.from || month_day <= s.to
+            }
+        })
+    }
+
+    /// Returns the day profile replacing the weekday for a date, or None if the date follows its
+    /// weekday. Calendar overrides go before holidays, and if overrides overlap the first one
+    /// given wins.
+    ///
+    /// # Arguments
+    ///
+    /// * 'date' - local date
+    pub fn profile(&self, date: NaiveDate) -> Option<&str> {
+        self.overrides.iter()
+            .find(|o| o.from <= date && date <= o.to)
+            .map(|o| o.profile.as_str())
+            .or_else(|| self.holiday_profile.as_deref().filter(|_| is_swedish_holiday(date)))
+    }
+
+    /// Returns the quarter hour consumption for a date, taken from a day profile if the date has
+    /// a calendar override or is a holiday, and otherwise from its weekday in the seasonal variant
+    /// valid for the date if any
+    ///
+    /// # Arguments
+    ///
+    /// * 'date' - local date
+    pub fn day(&self, date: NaiveDate) -> &[f64;96] {
+        let mut week_day = date.weekday().num_days_from_monday() as usize;
+        if let Some(profile) = self.profile(date) {
+            match WEEKDAYS.iter().position(|w| *w == profile) {
+                Some(w) => week_day = w,
+                None => if let Some(day) = self.profiles.get(profile) { return day },
+            }
+        }
+
+        match self.season(date) {
+            Some(season) => &self.seasons[season].days[week_day],
+            None => &self.days[week_day],
+        }
+    }
+}
@@ -1,21 +1,21 @@
 use std::{env, fs};
 use std::path::PathBuf;
-use log::info;
+use log::{info, warn};
 use anyhow::Result;
 use foxess::Fox;
 use thiserror::Error;
-use crate::config::{load_config, Config, LoadConfigurationError};
+use crate::config::{load_config, Config, LoadConfigurationError, Optimizer};
 use crate::consumption::Consumption;
 use crate::logging::{setup_logger, LoggerError};
-use crate::manager_forecast::{Forecast, ForecastError};
+use crate::manager_forecast::{forecast_provider, ForecastError, ForecastProvider};
 use crate::manager_mail::{Mail, MailError};
-use crate::manager_nordpool::{NordPool, NordPoolError};
+use crate::manager_prices::{Prices, PricesError};
 use crate::manager_production::PVProduction;
 
 pub struct Mgr {
     pub fox: Fox,
-    pub nordpool: NordPool,
-    pub forecast: Forecast,
+    pub prices: Prices,
+    pub forecast: Box<dyn ForecastProvider>,
     pub pv: PVProduction,
     pub cons: Consumption,
     pub mail: Mail,
@@ -38,6 +38,9 @@ pub fn init() -> Result<(Config, Mgr), InitializationError> {
     let mut config = load_config(&config_path)?;
     config.fox_ess.api_key = read_credential("fox_ess_api_key")?;
     config.fox_ess.inverter_sn = read_credential("fox_ess_inverter_sn")?;
+    if let Some(entsoe) = config.entsoe.as_mut() {
+        entsoe.api_token = read_credential("entsoe_api_token")?;
+    }
     config.mail.smtp_user = read_credential("mail_smtp_user")?;
     config.mail.smtp_password = read_credential("mail_smtp_password")?;
 
@@ -52,20 +55,23 @@ pub fn init() -> Result<(Config, Mgr), InitializationError> {
 
     // Print version
     info!("starting mygrid scheduler version: {}", env!("CARGO_PKG_VERSION"));
+    if config.charge.max_export_kw > 0.0 && config.scheduler.optimizer == Optimizer::Search {
+        warn!("max_export_kw is ignored, the search optimizer doesn't place Sell blocks (use optimizer \"dynamic_programming\")");
+    }
 
     
     // Instantiate structs
     let fox = Fox::new(&config.fox_ess.api_key, &config.fox_ess.inverter_sn, 30)?;
-    let nordpool = NordPool::new(&config.tariff_fees)?;
-    let smhi = Forecast::new(&config)?;
+    let prices = Prices::new(&config)?;
+    let forecast = forecast_provider(&config)?;
     let pv = PVProduction::new(&config.production, config.geo_ref.lat, config.geo_ref.long);
     let cons = Consumption::new(&config.consumption);
     let mail = Mail::new(&config.mail)?;
 
     let mgr = Mgr {
         fox,
-        nordpool,
-        forecast: smhi,
+        prices,
+        forecast,
         pv,
         cons,
         mail,
@@ -106,8 +112,8 @@ pub enum InitializationError {
     CredentialUtf8Error(#[from] std::string::FromUtf8Error),
     #[error("FoxInitializationError: {0}")]
     FoxInitializationError(#[from] foxess::FoxError),
-    #[error("NordPoolInitializationError: {0}")]
-    NordPoolInitializationError(#[from] NordPoolError),
+    #[error("PricesInitializationError: {0}")]
+    PricesInitializationError(#[from] PricesError),
     #[error("ForecastInitializationError: {0}")]
     ForecastInitializationError(#[from] ForecastError),
 }
@@ -0,0 +1,230 @@
+use anyhow::Result;
+use thiserror::Error;
+
+/// Result of a bounded least squares fit
+///
+pub struct LeastSquaresFit {
+    pub x: Vec<f64>,
+    pub residuals: Vec<f64>,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+/// Minimizes the sum of squared residuals within the given bounds using a projected
+/// Levenberg-Marquardt method. The Jacobian is estimated by finite differences, parameters held
+/// at a bound by the gradient are kept fixed, and each step is projected onto the bounds.
+///
+/// # Arguments
+///
+/// * 'residuals' - function returning the residuals for a parameter vector
+/// * 'x0' - initial parameter vector
+/// * 'lower' - lower bound per parameter
+/// * 'upper' - upper bound per parameter
+/// * 'max_iterations' - max number of accepted or rejected steps
+pub fn bounded_least_squares<F, E>(residuals: F, x0: &[f64], lower: &[f64], upper: &[f64], max_iterations: usize) -> Result<LeastSquaresFit, E>
+where
+    F: Fn(&[f64]) -> Result<Vec<f64>, E>,
+    E: From<LeastSquaresError>,
+{
+    let n = x0.len();
+    if lower.len() != n || upper.len() != n || (0..n).any(|j| lower[j] > upper[j]) {
+        return Err(LeastSquaresError::BoundsError("bounds must match parameters and lower must not exceed upper".to_string()).into());
+    }
+
+    let mut x: Vec<f64> = (0..n).map(|j| x0[j].clamp(lower[j], upper[j])).collect();
+    let mut r = residuals(&x)?;
+    let mut cost = sum_of_squares(&r);
+    let mut lambda = 1e-3;
+    let mut converged = false;
+    let mut iterations = 0;
+
+    while iterations < max_iterations && !converged {
+        iterations += 1;
+
+        // Forward (or backward if at the upper bound) difference Jacobian, one column per parameter
+        let mut jacobian: Vec<Vec<f64>> = Vec::with_capacity(n);
+        for j in 0..n {
+            let step = 1e-4 * (upper[j] - lower[j]).max(1e-6);
+            let step = if x[j] + step > upper[j] { -step } else { step };
+            let mut xs = x.clone();
+            xs[j] += step;
+            let rs = residuals(&xs)?;
+            if rs.len() != r.len() {
+                return Err(LeastSquaresError::ResidualsError("number of residuals changed between evaluations".to_string()).into());
+            }
+            jacobian.push(rs.iter().zip(r.iter()).map(|(a, b)| (a - b) / step).collect());
+        }
+
+        // Normal equations J'J and gradient J'r
+        let mut jtj = vec![vec![0.0; n]; n];
+        let mut jtr = vec![0.0; n];
+        for a in 0..n {
+            jtr[a] = jacobian[a].iter().zip(r.iter()).map(|(j, r)| j * r).sum();
+            for b in a..n {
+                let v: f64 = jacobian[a].iter().zip(jacobian[b].iter()).map(|(ja, jb)| ja * jb).sum();
+                jtj[a][b] = v;
+                jtj[b][a] = v;
+            }
+        }
+
+        // Parameters at a bound the gradient pushes against are kept fixed, so the step is
+        // solved for the free parameters only instead of being cut by the projection
+        let active: Vec<bool> = (0..n)
+            .map(|j| (x[j] <= lower[j] && jtr[j] > 0.0) || (x[j] >= upper[j] && jtr[j] < 0.0))
+            .collect();
+
+        // Increase damping until a step lowers the cost, or give up when the damping explodes
+        loop {
+            let mut a = jtj.clone();
+            for j in 0..n {
+                a[j][j] += lambda * jtj[j][j].max(1e-12);
+            }
+            let mut b: Vec<f64> = jtr.iter().map(|g| -g).collect();
+            for j in (0..n).filter(|j| active[*j]) {
+                (0..n).for_each(|k| { a[j][k] = 0.0; a[k][j] = 0.0; });
+                a[j][j] = 1.0;
+                b[j] = 0.0;
+            }
+
+            let Some(delta) = solve(a, b) else {
+                lambda *= 4.0;
+                if lambda > 1e10 { converged = true; break; }
+                continue;
+            };
+
+            let x_new: Vec<f64> = (0..n).map(|j| (x[j] + delta[j]).clamp(lower[j], upper[j])).collect();
+            let r_new = residuals(&x_new)?;
+            let cost_new = sum_of_squares(&r_new);
+
+            if cost_new < cost {
+                let step_size = (0..n).map(|j| ((x_new[j] - x[j]) / (upper[j] - lower[j]).max(1e-6)).abs()).fold(0.0, f64::max);
+                converged = (cost - cost_new) <= 1e-8 * cost || step_size < 1e-6;
+                x = x_new;
+                r = r_new;
+                cost = cost_new;
+                lambda = (lambda / 3.0).max(1e-9);
+                break;
+            }
+
+            lambda *= 4.0;
+            if lambda > 1e10 {
+                converged = true;
+                break;
+            }
+        }
+    }
+
+    Ok(LeastSquaresFit { x, residuals: r, iterations, converged })
+}
+
+/// Returns the sum of squares of the given values
+///
+/// # Arguments
+///
+/// * 'values' - values to sum the squares of
+fn sum_of_squares(values: &[f64]) -> f64 {
+    values.iter().map(|v| v * v).sum()
+}
+
+/// Solves the linear equation system a * x = b with Gaussian elimination and partial pivoting,
+/// or returns None if the system is singular
+///
+/// # Arguments
+///
+/// * 'a' - square coefficient matrix
+/// * 'b' - right hand side
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+
+    for col in 0..n {
+        let pivot = (col..n).max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-300 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let pivot_row = a[col].clone();
+        for row in col + 1..n {
+            let factor = a[row][col] / pivot_row[col];
+            a[row].iter_mut().zip(pivot_row.iter()).skip(col).for_each(|(v, p)| *v -= factor * p);
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+
+    Some(x)
+}
+
+/// Error depicting errors that occur while fitting parameters
+///
+#[derive(Debug, Error)]
+pub enum LeastSquaresError {
+    #[error("BoundsError: {0}")]
+    BoundsError(String),
+    #[error("ResidualsError: {0}")]
+    ResidualsError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits_exponential_decay_with_known_parameters() {
+        let t: Vec<f64> = (0..20).map(|i| i as f64 * 0.5).collect();
+        let y: Vec<f64> = t.iter().map(|t| 2.5 * (-0.3 * t).exp()).collect();
+        let residuals = |x: &[f64]| -> Result<Vec<f64>, LeastSquaresError> {
+            Ok(t.iter().zip(y.iter()).map(|(t, y)| x[0] * (x[1] * t).exp() - y).collect())
+        };
+
+        let fit = bounded_least_squares(residuals, &[1.0, -0.1], &[0.0, -2.0], &[10.0, 0.0], 100).unwrap();
+
+        assert!(fit.converged);
+        assert!((fit.x[0] - 2.5).abs() < 1e-4);
+        assert!((fit.x[1] + 0.3).abs() < 1e-4);
+        assert!(sum_of_squares(&fit.residuals) < 1e-8);
+    }
+
+    #[test]
+    fn fits_quadratic_with_known_parameters() {
+        let t: Vec<f64> = (-5..=5).map(|i| i as f64).collect();
+        let residuals = |x: &[f64]| -> Result<Vec<f64>, LeastSquaresError> {
+            Ok(t.iter().map(|t| x[0] + x[1] * t + x[2] * t * t - (1.0 + 2.0 * t - 0.5 * t * t)).collect())
+        };
+
+        let fit = bounded_least_squares(residuals, &[0.0, 0.0, 0.0], &[-10.0, -10.0, -10.0], &[10.0, 10.0, 10.0], 100).unwrap();
+
+        for (fitted, expected) in fit.x.iter().zip([1.0, 2.0, -0.5]) {
+            assert!((fitted - expected).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn stops_at_an_active_bound() {
+        // y = 1 + 3t with the slope bounded to 2, the best intercept is then the mean of y - 2t
+        let t: Vec<f64> = (0..=10).map(|i| i as f64).collect();
+        let residuals = |x: &[f64]| -> Result<Vec<f64>, LeastSquaresError> {
+            Ok(t.iter().map(|t| x[0] + x[1] * t - (1.0 + 3.0 * t)).collect())
+        };
+
+        let fit = bounded_least_squares(residuals, &[0.0, 1.0], &[-10.0, 0.0], &[10.0, 2.0], 100).unwrap();
+
+        assert_eq!(fit.x[1], 2.0);
+        assert!((fit.x[0] - 6.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rejects_inconsistent_bounds() {
+        let residuals = |x: &[f64]| -> Result<Vec<f64>, LeastSquaresError> { Ok(vec![x[0]]) };
+
+        let fit = bounded_least_squares(residuals, &[0.0], &[1.0], &[0.0], 10);
+
+        assert!(matches!(fit, Err(LeastSquaresError::BoundsError(_))));
+    }
+}
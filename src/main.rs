@@ -1,12 +1,19 @@
+use std::env;
 use rayon::ThreadPoolBuilder;
 use anyhow::Result;
 use log::error;
 use thiserror::Error;
+use foxess::Fox;
+use crate::calibration::{calibrate, fit_consumption_curve, learn_consumption_diagram, CalibrationError};
+use crate::config::Config;
 use crate::initialization::init;
 use crate::worker::run;
 
 mod scheduler;
 mod manager_nordpool;
+mod manager_entsoe;
+mod manager_prices;
+mod manager_price_file;
 mod manager_production;
 mod spline;
 mod manager_mail;
@@ -17,6 +24,11 @@ mod initialization;
 mod consumption;
 mod logging;
 mod macros;
+mod power_peaks;
+mod calendar;
+mod tariff_formula;
+mod least_squares;
+mod calibration;
 pub mod models;
 mod worker;
 
@@ -32,6 +44,22 @@ fn main() -> Result<()> {
         }   
     };
 
+    // Run a calibration command instead of scheduling if asked for
+    let commands: [(&str, &str, CalibrationCommand); 3] = [
+        ("--calibrate", "Calibration", calibrate),
+        ("--learn-consumption", "Learning consumption diagram", learn_consumption_diagram),
+        ("--fit-consumption-curve", "Fitting consumption curve", fit_consumption_curve),
+    ];
+    if let Some((_, name, command)) = commands.iter().find(|(arg, _, _)| env::args().any(|a| a == *arg)) {
+        return match command(&config, &mgr.fox) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("{} failed: {}", name, e);
+                Err(SchedulingError(format!("{} failed: {}", name, e)))?
+            }
+        };
+    }
+
     // Create a new schedule
     match run(&config, &mut mgr, &config.files, config.general.debug_run_time, config.general.debug_soc_soh_in) {
         Ok(_) => {
@@ -47,6 +75,9 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Command run instead of scheduling, given the configuration and a Fox ESS client
+type CalibrationCommand = fn(&Config, &Fox) -> Result<(), CalibrationError>;
+
 /// Error depicting errors that occur while creating and managing schedules
 ///
 #[derive(Debug, Error)]
@@ -0,0 +1,264 @@
+mod models;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use reqwest::blocking::Client;
+use anyhow::Result;
+use thiserror::Error;
+use crate::config::Config;
+use crate::manager_entsoe::models::{Period, PublicationMarketDocument};
+
+pub struct Entsoe {
+    client: Client,
+    api_token: String,
+    domain: String,
+    exchange_rate: f64,
+    currency: String,
+}
+
+impl Entsoe {
+    pub fn new(config: &Config) -> Result<Entsoe, EntsoeError> {
+        let entsoe = config.entsoe.as_ref()
+            .ok_or(EntsoeError::ConfigError("[entsoe] section missing".to_string()))?;
+
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+
+        Ok(Self {
+            client,
+            api_token: entsoe.api_token.clone(),
+            domain: entsoe.domain.clone(),
+            exchange_rate: entsoe.exchange_rate,
+            currency: config.nordpool.currency.clone(),
+        })
+    }
+
+    /// Returns the bidding zone (EIC code) prices are retrieved for
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Returns the currency prices are converted to by the exchange rate
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Retrieves day ahead spot prices (currency/kWh) per quarter from the ENTSO-E Transparency Platform
+    ///
+    /// # Arguments
+    ///
+    /// * 'day_start' - the start time of the day to retrieve prices for
+    /// * 'day_end' - the end time of the day to retrieve prices for (non-inclusive)
+    pub fn get_spot_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, EntsoeError> {
+        // https://web-api.tp.entsoe.eu/api?securityToken=...&documentType=A44&in_Domain=10Y1001A1001A47J&out_Domain=10Y1001A1001A47J&periodStart=202510212200&periodEnd=202510222200
+        let url = "https://web-api.tp.entsoe.eu/api";
+        let period_start = day_start.format("%Y%m%d%H%M").to_string();
+        let period_end = day_end.format("%Y%m%d%H%M").to_string();
+        let query = vec![
+            ("securityToken", self.api_token.as_str()),
+            ("documentType", "A44"),
+            ("in_Domain", self.domain.as_str()),
+            ("out_Domain", self.domain.as_str()),
+            ("periodStart", period_start.as_str()),
+            ("periodEnd", period_end.as_str()),
+        ];
+
+        let response = self.client
+            .get(url)
+            .query(&query)
+            .send()?;
+
+        if !response.status().is_success() {
+            return Err(EntsoeError::NoContentError(format!("status {}", response.status())));
+        }
+
+        let xml = response.text()?;
+
+        self.prices_from_xml(&xml, day_start, day_end)
+    }
+
+    /// Transforms an A44 day ahead prices document to a plain vector of prices
+    ///
+    /// # Arguments
+    ///
+    /// * 'xml' - the A44 document
+    /// * 'day_start' - start of day to transform prices for
+    /// * 'day_end' - end of day to transform prices for (non-inclusive)
+    pub fn prices_from_xml(&self, xml: &str, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, EntsoeError> {
+        let result: Vec<(DateTime<Utc>, f64)> = parse_day_ahead(xml)?.into_iter()
+            .filter(|(delivery_start, _)| *delivery_start >= day_start && *delivery_start < day_end)
+            .map(|(delivery_start, price)| (delivery_start, price * self.exchange_rate / 1000.0)) // EUR per MWh to currency per kWh
+            .collect();
+
+        if result.is_empty() {
+            Err(EntsoeError::NoContentError("no prices for requested period".to_string()))
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+/// Parses an A44 day ahead prices document into quarterly prices (EUR/MWh) ordered by time.
+/// Hourly (PT60M) points are expanded to four quarters, and positions left out of a period
+/// (curve type A03) repeat the price of the previous position. Where periods of different
+/// resolution overlap, the finer resolution is used.
+///
+/// # Arguments
+///
+/// * 'xml' - the A44 document
+pub fn parse_day_ahead(xml: &str) -> Result<Vec<(DateTime<Utc>, f64)>, EntsoeError> {
+    let document: PublicationMarketDocument = quick_xml::de::from_str(xml)?;
+
+    let mut periods: Vec<(i64, &Period)> = Vec::new();
+    for period in document.time_series.iter().flat_map(|ts| ts.periods.iter()) {
+        periods.push((resolution_minutes(&period.resolution)?, period));
+    }
+    periods.sort_by_key(|p| std::cmp::Reverse(p.0));
+
+    let mut quarters: BTreeMap<DateTime<Utc>, f64> = BTreeMap::new();
+    for (minutes, period) in periods {
+        let start = parse_time(&period.time_interval.start)?;
+        let end = parse_time(&period.time_interval.end)?;
+        let positions = ((end - start).num_minutes() / minutes) as usize;
+
+        let mut points = period.points.iter().peekable();
+        let mut price: Option<f64> = None;
+        for position in 1..=positions {
+            if let Some(point) = points.next_if(|p| p.position == position) {
+                price = Some(point.price_amount);
+            }
+            let Some(price) = price else {
+                return Err(EntsoeError::DocumentError(format!("missing first point in period starting {}", start)));
+            };
+
+            let position_start = start + TimeDelta::minutes((position as i64 - 1) * minutes);
+            for quarter in 0..minutes / 15 {
+                quarters.insert(position_start + TimeDelta::minutes(quarter * 15), price);
+            }
+        }
+    }
+
+    if quarters.is_empty() {
+        Err(EntsoeError::NoContentError("document contains no prices".to_string()))
+    } else {
+        Ok(quarters.into_iter().collect())
+    }
+}
+
+/// Returns the length in minutes of a period resolution
+///
+/// # Arguments
+///
+/// * 'resolution' - ISO 8601 duration as given in the document, i.e. PT15M or PT60M
+fn resolution_minutes(resolution: &str) -> Result<i64, EntsoeError> {
+    match resolution {
+        "PT15M" => Ok(15),
+        "PT30M" => Ok(30),
+        "PT60M" => Ok(60),
+        _ => Err(EntsoeError::DocumentError(format!("unsupported resolution {}", resolution))),
+    }
+}
+
+/// Parses a time interval time stamp, e.g. 2025-10-21T22:00Z
+///
+/// # Arguments
+///
+/// * 'time' - the time stamp
+fn parse_time(time: &str) -> Result<DateTime<Utc>, EntsoeError> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%MZ")
+        .map(|t| t.and_utc())
+        .map_err(|e| EntsoeError::DocumentError(format!("invalid time {}: {}", time, e)))
+}
+
+#[derive(Error, Debug)]
+pub enum EntsoeError {
+    #[error("ConfigError: {0}")]
+    ConfigError(String),
+    #[error("DocumentError: {0}")]
+    DocumentError(String),
+    #[error("XmlError: {0}")]
+    XmlError(#[from] quick_xml::DeError),
+    #[error("NetworkError: {0}")]
+    NetworkError(#[from] reqwest::Error),
+    #[error("NoContentError: {0}")]
+    NoContentError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PT15M: &str = include_str!("../../tests/fixtures/entsoe_a44_pt15m.xml");
+    const PT60M: &str = include_str!("../../tests/fixtures/entsoe_a44_pt60m.xml");
+    const A03: &str = include_str!("../../tests/fixtures/entsoe_a44_a03.xml");
+    const ACKNOWLEDGEMENT: &str = include_str!("../../tests/fixtures/entsoe_acknowledgement.xml");
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn entsoe() -> Entsoe {
+        Entsoe {
+            client: Client::new(),
+            api_token: String::new(),
+            domain: "10Y1001A1001A47J".to_string(),
+            exchange_rate: 11.0,
+            currency: "SEK".to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_quarter_resolution() {
+        let prices = parse_day_ahead(PT15M).unwrap();
+
+        assert_eq!(prices.len(), 96);
+        assert_eq!(prices[0], (utc("2025-10-21T22:00:00Z"), 37.29));
+        assert_eq!(prices[1], (utc("2025-10-21T22:15:00Z"), 36.69));
+        assert_eq!(prices[95], (utc("2025-10-22T21:45:00Z"), 25.26));
+    }
+
+    #[test]
+    fn expands_hourly_resolution_to_quarters() {
+        let prices = parse_day_ahead(PT60M).unwrap();
+
+        assert_eq!(prices.len(), 96);
+        assert!(prices[0..4].iter().all(|p| p.1 == 37.29));
+        assert_eq!(prices[4], (utc("2025-10-21T23:00:00Z"), 35.34));
+        assert_eq!(prices[95], (utc("2025-10-22T21:45:00Z"), 31.33));
+    }
+
+    #[test]
+    fn repeats_price_for_omitted_positions() {
+        let prices = parse_day_ahead(A03).unwrap();
+
+        assert_eq!(prices.len(), 96);
+        assert_eq!(prices[1].1, 36.69);
+        assert_eq!(prices[2], (utc("2025-10-21T22:30:00Z"), 36.69));
+        assert_eq!(prices[3].1, 36.69);
+        assert_eq!(prices[4].1, 35.34);
+        assert!(prices[89..].iter().all(|p| p.1 == 38.51));
+    }
+
+    #[test]
+    fn acknowledgement_has_no_content() {
+        assert!(matches!(parse_day_ahead(ACKNOWLEDGEMENT), Err(EntsoeError::NoContentError(_))));
+    }
+
+    #[test]
+    fn converts_prices_within_period() {
+        let prices = entsoe().prices_from_xml(PT60M, utc("2025-10-21T23:00:00Z"), utc("2025-10-22T00:00:00Z")).unwrap();
+
+        assert_eq!(prices.len(), 4);
+        assert_eq!(prices[0].0, utc("2025-10-21T23:00:00Z"));
+        assert!(prices.iter().all(|p| (p.1 - 0.38874).abs() < 1e-9));
+    }
+
+    #[test]
+    fn acknowledgement_gives_no_prices() {
+        let result = entsoe().prices_from_xml(ACKNOWLEDGEMENT, utc("2025-10-22T22:00:00Z"), utc("2025-10-23T22:00:00Z"));
+
+        assert!(matches!(result, Err(EntsoeError::NoContentError(_))));
+    }
+}
@@ -0,0 +1,35 @@
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug)]
+pub struct TimeInterval {
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Point {
+    pub position: usize,
+    #[serde(rename = "price.amount")]
+    pub price_amount: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Period {
+    #[serde(rename = "timeInterval")]
+    pub time_interval: TimeInterval,
+    pub resolution: String,
+    #[serde(rename = "Point", default)]
+    pub points: Vec<Point>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TimeSeries {
+    #[serde(rename = "Period", default)]
+    pub periods: Vec<Period>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PublicationMarketDocument {
+    #[serde(rename = "TimeSeries", default)]
+    pub time_series: Vec<TimeSeries>,
+}
@@ -1,72 +1,83 @@
 mod models;
+mod mygrid;
+mod smhi;
+mod open_meteo;
 
-use std::time::Duration;
 use chrono::{DateTime, DurationRound, TimeDelta, Utc};
-use reqwest::blocking::Client;
 use anyhow::Result;
 use thiserror::Error;
-use crate::config::Config;
+use crate::config::{Config, ForecastSource, ProductionParameters};
 use crate::models::{ForecastValue, ForecastValues};
 use crate::manager_forecast::models::ForecastRecord;
+use crate::manager_forecast::mygrid::MygridForecast;
+use crate::manager_forecast::open_meteo::OpenMeteoForecast;
+use crate::manager_forecast::smhi::SmhiForecast;
 
-/// Struct for managing whether forecasts
-pub struct Forecast {
-    client: Client,
-    host: String,
-    port: u16,
+/// A source of weather forecasts
+pub trait ForecastProvider {
+    /// Retrieves a weather forecast for the given period
+    ///
+    /// # Arguments
+    ///
+    /// * 'from' - the datetime to get forecast from
+    /// * 'to' - the datetime to get forecast to (non-inclusive)
+    fn new_forecast(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<ForecastValues, ForecastError>;
+}
+
+/// Returns the forecast provider chosen in configuration
+///
+/// # Arguments
+///
+/// * 'config' - configuration to use
+pub fn forecast_provider(config: &Config) -> Result<Box<dyn ForecastProvider>, ForecastError> {
+    Ok(match config.forecast.provider {
+        ForecastSource::Mygrid => Box::new(MygridForecast::new(config)?),
+        ForecastSource::Smhi => Box::new(SmhiForecast::new(config)?),
+        ForecastSource::OpenMeteo => Box::new(OpenMeteoForecast::new(config)?),
+    })
+}
+
+/// How much clouds in each layer can block PV power
+pub struct CloudFactors {
     high_clouds_factor: f64,
     mid_clouds_factor: f64,
     low_clouds_factor: f64,
 }
 
-impl Forecast {
-    /// Returns a forecast struct ready for fetching and processing whether forecasts
+impl CloudFactors {
+    /// Returns cloud factors from the production configuration
     ///
     /// # Arguments
     ///
     /// * 'config' - configuration to use
-    pub fn new(config: &Config) -> Result<Forecast, ForecastError> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()?;
-
-        Ok(Forecast {
-            client,
-            host: config.forecast.host.clone(),
-            port: config.forecast.port,           
-            high_clouds_factor: config.production.high_clouds_factor,
-            mid_clouds_factor: config.production.mid_clouds_factor,
-            low_clouds_factor: config.production.low_clouds_factor, 
-        })
+    pub fn new(config: &Config) -> CloudFactors {
+        CloudFactors::from_production(&config.production)
     }
 
-    /// Retrieves a weather forecast for the given date
+    /// Returns cloud factors from production parameters
     ///
     /// # Arguments
     ///
-    /// * 'from' - the datetime to get forecast from
-    /// * 'to' - the datetime to get forecast to (non-inclusive)
-    pub fn new_forecast(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<ForecastValues, ForecastError> {
-        let from = from.duration_trunc(TimeDelta::hours(1))
-            .map_err(|e| ForecastError::DateError(format!("from date: {}", e.to_string())))?;
-        let to = to.duration_trunc(TimeDelta::hours(1))
-            .map_err(|e| ForecastError::DateError(format!("to date: {}", e.to_string())))?;
-
-        let url = format!("http://{}:{}/forecast", self.host, self.port);
-
-        let response = self.client
-            .get(url)
-            .query(&vec![("id", "smhi"), ("from", &from.to_rfc3339()), ("to", &to.to_rfc3339())])
-            .send()?;
-        
-        let json = response.text()?;
-
-        let tmp_forecast: Vec<ForecastRecord> = serde_json::from_str(&json)
-            .map_err(|e| ForecastError::ParseError(e.to_string()))?;
+    /// * 'params' - production parameters to use
+    pub fn from_production(params: &ProductionParameters) -> CloudFactors {
+        CloudFactors {
+            high_clouds_factor: params.high_clouds_factor,
+            mid_clouds_factor: params.mid_clouds_factor,
+            low_clouds_factor: params.low_clouds_factor,
+        }
+    }
 
+    /// Converts forecast records to forecast values
+    ///
+    /// # Arguments
+    ///
+    /// * 'records' - forecast records as given by a provider
+    /// * 'from' - the datetime the forecast was requested from
+    /// * 'to' - the datetime the forecast was requested to (non-inclusive)
+    pub fn forecast_values(&self, records: Vec<ForecastRecord>, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<ForecastValues, ForecastError> {
         let mut forecast: Vec<ForecastValue> = Vec::new();
 
-        for fr in tmp_forecast {
+        for fr in records {
             let (lcc_mean, mcc_mean, hcc_mean, cloud_factor) = self.cloud_factor(fr.lcc_mean, fr.mcc_mean, fr.hcc_mean);
             let fc = ForecastValue {
                 valid_time: fr.date_time,
@@ -75,6 +86,9 @@ impl Forecast {
                 mcc_mean,
                 hcc_mean,
                 cloud_factor,
+                ghi: fr.ghi,
+                dni: fr.dni,
+                dhi: fr.dhi,
             };
 
             forecast.push(fc);
@@ -95,7 +109,7 @@ impl Forecast {
     /// * 'lcc_mean' - low height cloud factor from forecast (0-8)
     /// * 'mcc_mean' - medium height cloud factor from forecast (0-8)
     /// * 'hcc_mean' - high height cloud factor from forecast (0-8) 
-    fn cloud_factor(&self, lcc_mean: u8, mcc_mean: u8, hcc_mean: u8) -> (f64, f64, f64, f64) {
+    pub fn cloud_factor(&self, lcc_mean: u8, mcc_mean: u8, hcc_mean: u8) -> (f64, f64, f64, f64) {
         let lcc_mean = lcc_mean as f64;
         let mcc_mean = mcc_mean as f64;
         let hcc_mean = hcc_mean as f64;
@@ -108,6 +122,21 @@ impl Forecast {
     }
 }
 
+/// Truncates a forecast period to whole hours
+///
+/// # Arguments
+///
+/// * 'from' - the datetime to get forecast from
+/// * 'to' - the datetime to get forecast to (non-inclusive)
+fn whole_hours(from: DateTime<Utc>, to: DateTime<Utc>) -> Result<(DateTime<Utc>, DateTime<Utc>), ForecastError> {
+    let from = from.duration_trunc(TimeDelta::hours(1))
+        .map_err(|e| ForecastError::DateError(format!("from date: {}", e)))?;
+    let to = to.duration_trunc(TimeDelta::hours(1))
+        .map_err(|e| ForecastError::DateError(format!("to date: {}", e)))?;
+
+    Ok((from, to))
+}
+
 #[derive(Error, Debug)]
 pub enum ForecastError {
     #[error("DateError: {0}")]
@@ -118,4 +147,4 @@ pub enum ForecastError {
     EmptyForecastError(String),
     #[error("NetworkError: {0}")]
     NetworkError(#[from] reqwest::Error),
-}
\ No newline at end of file
+}
@@ -8,4 +8,49 @@ pub struct ForecastRecord {
     pub lcc_mean: u8,
     pub mcc_mean: u8,
     pub hcc_mean: u8,
+    #[serde(default)]
+    pub ghi: Option<f64>,
+    #[serde(default)]
+    pub dni: Option<f64>,
+    #[serde(default)]
+    pub dhi: Option<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct SmhiParameter {
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+#[derive(Deserialize)]
+pub struct SmhiTimeSeries {
+    #[serde(rename = "validTime")]
+    pub valid_time: DateTime<Utc>,
+    pub parameters: Vec<SmhiParameter>,
+}
+
+#[derive(Deserialize)]
+pub struct SmhiDocument {
+    #[serde(rename = "timeSeries")]
+    pub time_series: Vec<SmhiTimeSeries>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenMeteoHourly {
+    pub time: Vec<String>,
+    pub temperature_2m: Vec<Option<f64>>,
+    pub cloud_cover_low: Vec<Option<f64>>,
+    pub cloud_cover_mid: Vec<Option<f64>>,
+    pub cloud_cover_high: Vec<Option<f64>>,
+    #[serde(default)]
+    pub shortwave_radiation: Vec<Option<f64>>,
+    #[serde(default)]
+    pub direct_normal_irradiance: Vec<Option<f64>>,
+    #[serde(default)]
+    pub diffuse_radiation: Vec<Option<f64>>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenMeteoDocument {
+    pub hourly: OpenMeteoHourly,
 }
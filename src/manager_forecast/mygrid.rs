@@ -0,0 +1,55 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use reqwest::blocking::Client;
+use crate::config::Config;
+use crate::manager_forecast::{whole_hours, CloudFactors, ForecastError, ForecastProvider};
+use crate::manager_forecast::models::ForecastRecord;
+use crate::models::ForecastValues;
+
+/// Forecast provider for the mygrid forecast server
+pub struct MygridForecast {
+    client: Client,
+    host: String,
+    port: u16,
+    cloud_factors: CloudFactors,
+}
+
+impl MygridForecast {
+    /// Returns a forecast struct ready for fetching and processing whether forecasts
+    ///
+    /// # Arguments
+    ///
+    /// * 'config' - configuration to use
+    pub fn new(config: &Config) -> Result<MygridForecast, ForecastError> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+
+        Ok(MygridForecast {
+            client,
+            host: config.forecast.host.clone(),
+            port: config.forecast.port,
+            cloud_factors: CloudFactors::new(config),
+        })
+    }
+}
+
+impl ForecastProvider for MygridForecast {
+    fn new_forecast(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<ForecastValues, ForecastError> {
+        let (from, to) = whole_hours(from, to)?;
+
+        let url = format!("http://{}:{}/forecast", self.host, self.port);
+
+        let response = self.client
+            .get(url)
+            .query(&vec![("id", "smhi"), ("from", &from.to_rfc3339()), ("to", &to.to_rfc3339())])
+            .send()?;
+
+        let json = response.text()?;
+
+        let records: Vec<ForecastRecord> = serde_json::from_str(&json)
+            .map_err(|e| ForecastError::ParseError(e.to_string()))?;
+
+        self.cloud_factors.forecast_values(records, from, to)
+    }
+}
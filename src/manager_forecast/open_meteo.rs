@@ -0,0 +1,175 @@
+use std::time::Duration;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use reqwest::blocking::Client;
+use crate::config::Config;
+use crate::manager_forecast::{whole_hours, CloudFactors, ForecastError, ForecastProvider};
+use crate::manager_forecast::models::{ForecastRecord, OpenMeteoDocument};
+use crate::models::ForecastValues;
+
+/// Forecast provider for Open-Meteo compatible forecast APIs
+pub struct OpenMeteoForecast {
+    client: Client,
+    url: String,
+    lat: f64,
+    long: f64,
+    cloud_factors: CloudFactors,
+}
+
+impl OpenMeteoForecast {
+    /// Returns a forecast struct ready for fetching and processing whether forecasts
+    ///
+    /// # Arguments
+    ///
+    /// * 'config' - configuration to use
+    pub fn new(config: &Config) -> Result<OpenMeteoForecast, ForecastError> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+
+        Ok(OpenMeteoForecast {
+            client,
+            url: config.forecast.url.clone().unwrap_or("https://api.open-meteo.com/v1/forecast".to_string()),
+            lat: config.geo_ref.lat,
+            long: config.geo_ref.long,
+            cloud_factors: CloudFactors::new(config),
+        })
+    }
+}
+
+impl ForecastProvider for OpenMeteoForecast {
+    fn new_forecast(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<ForecastValues, ForecastError> {
+        let (from, to) = whole_hours(from, to)?;
+
+        let lat = self.lat.to_string();
+        let long = self.long.to_string();
+        let start_hour = from.format("%Y-%m-%dT%H:%M").to_string();
+        let end_hour = to.format("%Y-%m-%dT%H:%M").to_string();
+        let query = vec![
+            ("latitude", lat.as_str()),
+            ("longitude", long.as_str()),
+            ("hourly", "temperature_2m,cloud_cover_low,cloud_cover_mid,cloud_cover_high,shortwave_radiation,direct_normal_irradiance,diffuse_radiation"),
+            ("timezone", "GMT"),
+            ("start_hour", start_hour.as_str()),
+            ("end_hour", end_hour.as_str()),
+        ];
+
+        let json = self.client
+            .get(&self.url)
+            .query(&query)
+            .send()?
+            .error_for_status()?
+            .text()?;
+
+        let records = open_meteo_records(&json, from, to)?;
+
+        self.cloud_factors.forecast_values(records, from, to)
+    }
+}
+
+/// Parses an Open-Meteo hourly forecast, with times in GMT, into forecast records for the
+/// given period. Cloud cover is converted from percent to octas (0-8), and hours with missing
+/// values are skipped. Radiation is given as the mean over the preceding hour, so the value at
+/// the start of an hour is taken as the average of the means of the hours before and after.
+/// Radiation is left out if not given in the document.
+///
+/// # Arguments
+///
+/// * 'json' - the forecast document
+/// * 'from' - the datetime to get forecast from
+/// * 'to' - the datetime to get forecast to (non-inclusive)
+pub fn open_meteo_records(json: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<ForecastRecord>, ForecastError> {
+    let document: OpenMeteoDocument = serde_json::from_str(json)
+        .map_err(|e| ForecastError::ParseError(e.to_string()))?;
+    let hourly = document.hourly;
+
+    let octas = |percent: f64| (percent / 12.5).round().clamp(0.0, 8.0) as u8;
+
+    let mut records: Vec<ForecastRecord> = Vec::new();
+    for (i, time) in hourly.time.iter().enumerate() {
+        let date_time = NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M")
+            .map_err(|e| ForecastError::ParseError(format!("invalid time {}: {}", time, e)))?
+            .and_utc();
+        if date_time < from || date_time >= to {
+            continue;
+        }
+
+        let value = |values: &Vec<Option<f64>>| values.get(i).copied().flatten();
+        let radiation = |values: &Vec<Option<f64>>| match (value(values), values.get(i + 1).copied().flatten()) {
+            (Some(before), Some(after)) => Some((before + after) / 2.0),
+            (before, after) => before.or(after),
+        };
+        if let (Some(temperature), Some(low), Some(mid), Some(high)) = (
+            value(&hourly.temperature_2m),
+            value(&hourly.cloud_cover_low),
+            value(&hourly.cloud_cover_mid),
+            value(&hourly.cloud_cover_high)) {
+            records.push(ForecastRecord {
+                date_time,
+                temperature,
+                lcc_mean: octas(low),
+                mcc_mean: octas(mid),
+                hcc_mean: octas(high),
+                ghi: radiation(&hourly.shortwave_radiation),
+                dni: radiation(&hourly.direct_normal_irradiance),
+                dhi: radiation(&hourly.diffuse_radiation),
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOURLY: &str = include_str!("../../tests/fixtures/open_meteo_hourly.json");
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn record<'a>(records: &'a [ForecastRecord], time: &str) -> &'a ForecastRecord {
+        records.iter().find(|r| r.date_time == utc(time)).unwrap()
+    }
+
+    #[test]
+    fn gives_records_within_period_skipping_missing_values() {
+        let day = open_meteo_records(HOURLY, utc("2025-10-21T00:00:00Z"), utc("2025-10-22T00:00:00Z")).unwrap();
+        let morning = open_meteo_records(HOURLY, utc("2025-10-21T06:00:00Z"), utc("2025-10-21T09:00:00Z")).unwrap();
+
+        assert_eq!(day.len(), 23);
+        assert!(day.iter().all(|r| r.date_time != utc("2025-10-21T22:00:00Z")));
+        assert_eq!(morning.iter().map(|r| r.date_time).collect::<Vec<DateTime<Utc>>>(),
+            vec![utc("2025-10-21T06:00:00Z"), utc("2025-10-21T07:00:00Z"), utc("2025-10-21T08:00:00Z")]);
+        assert_eq!(morning[2].temperature, 7.9);
+    }
+
+    #[test]
+    fn converts_cloud_cover_percent_to_octas() {
+        let records = open_meteo_records(HOURLY, utc("2025-10-21T00:00:00Z"), utc("2025-10-22T00:00:00Z")).unwrap();
+
+        let octas = |time: &str| {
+            let r = record(&records, time);
+            (r.lcc_mean, r.mcc_mean, r.hcc_mean)
+        };
+        assert_eq!(octas("2025-10-21T00:00:00Z"), (8, 4, 0));   // 100%, 45%, 0%
+        assert_eq!(octas("2025-10-21T03:00:00Z"), (7, 2, 0));   // 87%, 31%, 4%
+        assert_eq!(octas("2025-10-21T04:00:00Z"), (5, 1, 1));   // 64%, 12%, 17%
+    }
+
+    #[test]
+    fn averages_radiation_over_the_hours_around_the_record() {
+        let records = open_meteo_records(HOURLY, utc("2025-10-21T00:00:00Z"), utc("2025-10-22T00:00:00Z")).unwrap();
+
+        let noon = record(&records, "2025-10-21T12:00:00Z");
+        assert_eq!(noon.ghi, Some(334.0));
+        assert!((noon.dni.unwrap() - 577.65).abs() < 1e-9);
+        assert_eq!(noon.dhi, Some(94.5));
+
+        // A missing mean on either side leaves the other one, missing on both sides gives none
+        assert_eq!(record(&records, "2025-10-21T17:00:00Z").ghi, Some(42.0));
+        assert_eq!(record(&records, "2025-10-21T18:00:00Z").ghi, Some(0.0));
+        assert_eq!(record(&records, "2025-10-21T23:00:00Z").ghi, None);
+    }
+}
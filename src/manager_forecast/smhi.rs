@@ -0,0 +1,208 @@
+use std::time::Duration;
+use chrono::{DateTime, TimeDelta, Utc};
+use reqwest::blocking::Client;
+use crate::config::Config;
+use crate::manager_forecast::{whole_hours, CloudFactors, ForecastError, ForecastProvider};
+use crate::manager_forecast::models::{ForecastRecord, SmhiDocument, SmhiTimeSeries};
+use crate::models::ForecastValues;
+
+/// Forecast provider for SMHI open data point forecasts
+pub struct SmhiForecast {
+    client: Client,
+    lat: f64,
+    long: f64,
+    cloud_factors: CloudFactors,
+}
+
+impl SmhiForecast {
+    /// Returns a forecast struct ready for fetching and processing whether forecasts
+    ///
+    /// # Arguments
+    ///
+    /// * 'config' - configuration to use
+    pub fn new(config: &Config) -> Result<SmhiForecast, ForecastError> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()?;
+
+        Ok(SmhiForecast {
+            client,
+            lat: config.geo_ref.lat,
+            long: config.geo_ref.long,
+            cloud_factors: CloudFactors::new(config),
+        })
+    }
+}
+
+impl ForecastProvider for SmhiForecast {
+    fn new_forecast(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<ForecastValues, ForecastError> {
+        let (from, to) = whole_hours(from, to)?;
+
+        let url = format!(
+            "https://opendata-download-metfcst.smhi.se/api/category/pmp3g/version/2/geotype/point/lon/{:.4}/lat/{:.4}/data.json",
+            self.long, self.lat);
+
+        let json = self.client
+            .get(url)
+            .send()?
+            .error_for_status()?
+            .text()?;
+
+        let records = smhi_records(&json, from, to)?;
+
+        self.cloud_factors.forecast_values(records, from, to)
+    }
+}
+
+/// Parses an SMHI open data point forecast (pmp3g) into hourly forecast records for the given
+/// period. SMHI gives hourly values for the first couple of days and then sparser, values in
+/// between are linearly interpolated. Hours before the first forecast time are given the
+/// first forecast values.
+///
+/// # Arguments
+///
+/// * 'json' - the point forecast document
+/// * 'from' - the datetime to get forecast from
+/// * 'to' - the datetime to get forecast to (non-inclusive)
+pub fn smhi_records(json: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<ForecastRecord>, ForecastError> {
+    let smhi: SmhiDocument = serde_json::from_str(json)
+        .map_err(|e| ForecastError::ParseError(e.to_string()))?;
+
+    let mut points: Vec<(DateTime<Utc>, [f64; 4])> = Vec::new();
+    for ts in smhi.time_series.iter() {
+        points.push((ts.valid_time, [
+            parameter(ts, "t")?,
+            parameter(ts, "lcc_mean")?,
+            parameter(ts, "mcc_mean")?,
+            parameter(ts, "hcc_mean")?,
+        ]));
+    }
+    points.sort_by_key(|p| p.0);
+
+    let mut records: Vec<ForecastRecord> = Vec::new();
+    let mut hour = from;
+    while hour < to {
+        if let Some(values) = interpolate(&points, hour) {
+            records.push(ForecastRecord {
+                date_time: hour,
+                temperature: values[0],
+                lcc_mean: values[1].round().clamp(0.0, 8.0) as u8,
+                mcc_mean: values[2].round().clamp(0.0, 8.0) as u8,
+                hcc_mean: values[3].round().clamp(0.0, 8.0) as u8,
+                ghi: None,
+                dni: None,
+                dhi: None,
+            });
+        }
+        hour += TimeDelta::hours(1);
+    }
+
+    Ok(records)
+}
+
+/// Returns the first value of a named parameter in a time series entry
+///
+/// # Arguments
+///
+/// * 'ts' - the time series entry
+/// * 'name' - name of the parameter
+fn parameter(ts: &SmhiTimeSeries, name: &str) -> Result<f64, ForecastError> {
+    ts.parameters.iter()
+        .find(|p| p.name == name)
+        .and_then(|p| p.values.first().copied())
+        .ok_or(ForecastError::ParseError(format!("parameter {} missing at {}", name, ts.valid_time)))
+}
+
+/// Linearly interpolates forecast values at the given time, None if after the last point
+///
+/// # Arguments
+///
+/// * 'points' - forecast values ordered by time
+/// * 'time' - the time to interpolate values at
+fn interpolate(points: &[(DateTime<Utc>, [f64; 4])], time: DateTime<Utc>) -> Option<[f64; 4]> {
+    let next = points.iter().position(|p| p.0 >= time)?;
+    if next == 0 || points[next].0 == time {
+        return Some(points[next].1);
+    }
+
+    let (t0, v0) = points[next - 1];
+    let (t1, v1) = points[next];
+    let w = (time - t0).num_seconds() as f64 / (t1 - t0).num_seconds() as f64;
+
+    Some([0, 1, 2, 3].map(|i| v0[i] + (v1[i] - v0[i]) * w))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PMP3G: &str = include_str!("../../tests/fixtures/smhi_pmp3g.json");
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn octas(record: &ForecastRecord) -> (u8, u8, u8) {
+        (record.lcc_mean, record.mcc_mean, record.hcc_mean)
+    }
+
+    #[test]
+    fn gives_hourly_records_over_the_forecast() {
+        let records = smhi_records(PMP3G, utc("2025-10-21T09:00:00Z"), utc("2025-10-22T20:00:00Z")).unwrap();
+
+        assert_eq!(records.len(), 33);
+        assert_eq!(records[0].date_time, utc("2025-10-21T09:00:00Z"));
+        assert_eq!(records[32].date_time, utc("2025-10-22T17:00:00Z"));
+        assert!(records.windows(2).all(|r| r[1].date_time - r[0].date_time == TimeDelta::hours(1)));
+    }
+
+    #[test]
+    fn keeps_hourly_values_and_first_values_before_start() {
+        let records = smhi_records(PMP3G, utc("2025-10-21T09:00:00Z"), utc("2025-10-21T15:00:00Z")).unwrap();
+
+        assert_eq!(records[0].temperature, 11.3);
+        assert_eq!(octas(&records[0]), (1, 0, 6));
+        assert_eq!(records[2].temperature, 11.3);
+        assert_eq!(records[5].temperature, 12.0);
+        assert_eq!(octas(&records[5]), (2, 1, 5));
+    }
+
+    #[test]
+    fn interpolates_between_sparse_values() {
+        let records = smhi_records(PMP3G, utc("2025-10-21T21:00:00Z"), utc("2025-10-22T09:00:00Z")).unwrap();
+
+        // Three hour steps from 20:00 (7.6, 7/4/1 octas) to 23:00 (6.1, 4/1/0 octas)
+        assert!((records[0].temperature - 7.1).abs() < 1e-9);
+        assert_eq!(octas(&records[0]), (6, 3, 1));
+        assert!((records[1].temperature - 6.6).abs() < 1e-9);
+        assert_eq!(octas(&records[1]), (5, 2, 0));
+
+        // Six hour steps from 05:00 (4.6, 3/0/1 octas) to 11:00 (8.4, 8/3/2 octas), octas are rounded
+        assert!((records[11].temperature - 6.5).abs() < 1e-9);
+        assert_eq!(octas(&records[11]), (6, 2, 2));
+    }
+
+    #[test]
+    fn maps_cloud_layers_to_cloud_factor() {
+        let cloud_factors = CloudFactors {
+            high_clouds_factor: 0.2,
+            mid_clouds_factor: 0.5,
+            low_clouds_factor: 1.0,
+        };
+        let from = utc("2025-10-21T11:00:00Z");
+        let to = utc("2025-10-21T12:00:00Z");
+        let records = smhi_records(PMP3G, from, to).unwrap();
+
+        let forecast = cloud_factors.forecast_values(records, from, to).unwrap().forecast;
+
+        assert_eq!((forecast[0].lcc_mean, forecast[0].mcc_mean, forecast[0].hcc_mean), (1.0, 0.0, 6.0));
+        assert!((forecast[0].cloud_factor - (1.0 - 6.0 / 8.0 * 0.2) * (1.0 - 1.0 / 8.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn gives_no_records_after_the_forecast() {
+        let records = smhi_records(PMP3G, utc("2025-10-22T18:00:00Z"), utc("2025-10-22T22:00:00Z")).unwrap();
+
+        assert!(records.is_empty());
+    }
+}
@@ -5,57 +5,52 @@ use chrono::{DateTime, DurationRound, TimeDelta, Utc};
 use reqwest::blocking::Client;
 use anyhow::Result;
 use thiserror::Error;
-use crate::models::{TariffValue};
-use crate::config::TariffFees;
+use crate::config::Config;
 use crate::manager_nordpool::models::Tariffs;
 
 pub struct NordPool {
     client: Client,
-    variable_fee: f64,
-    spot_fee_percentage: f64,
-    energy_tax: f64,
-    swedish_power_grid: f64,
-    balance_responsibility: f64,
-    electric_certificate: f64,
-    guarantees_of_origin: f64,
-    fixed: f64,
-    production_price: f64,
+    area: String,
+    currency: String,
 }
 
 impl NordPool {
-    pub fn new(config: &TariffFees) -> Result<NordPool, NordPoolError> {
+    pub fn new(config: &Config) -> Result<NordPool, NordPoolError> {
         let client = Client::builder()
             .timeout(Duration::from_secs(30))
             .build()?;
 
         Ok(Self {
             client,
-            variable_fee: config.variable_fee,
-            spot_fee_percentage: config.spot_fee_percentage / 100.0,
-            energy_tax: config.energy_tax,
-            swedish_power_grid: config.swedish_power_grid,
-            balance_responsibility: config.balance_responsibility,
-            electric_certificate: config.electric_certificate,
-            guarantees_of_origin: config.guarantees_of_origin,
-            fixed: config.fixed,
-            production_price: config.production_price,
+            area: config.nordpool.area.clone(),
+            currency: config.nordpool.currency.clone(),
         })
     }
 
-    /// Retrieves day ahead prices from NordPool
-    /// It gets the tariffs for the day indicated by date_time (if it can't an error will be returned),
+    /// Returns the delivery area prices are retrieved for
+    pub fn area(&self) -> &str {
+        &self.area
+    }
+
+    /// Returns the currency prices are retrieved in
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    /// Retrieves day ahead spot prices (currency/kWh) per quarter from NordPool
+    /// It gets the prices for the day indicated by date_time (if it can't an error will be returned),
     ///
     /// # Arguments
     ///
     /// * 'day_start' - the start time of the day to retrieve prices for
     /// * 'day_end' - the end time of the day to retrieve prices for (non-inclusive)
-    pub fn get_tariffs(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<TariffValue>, NordPoolError> {
+    pub fn get_spot_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, NordPoolError> {
 
         let mut day_date_utc = day_end.duration_trunc(TimeDelta::days(1)).unwrap();
-        let mut result = self.get_day_tariffs(day_start, day_end, day_date_utc)?;
-        while day_start < result.first().unwrap().valid_time {
+        let mut result = self.get_day_prices(day_start, day_end, day_date_utc)?;
+        while day_start < result.first().unwrap().0 {
             day_date_utc = day_date_utc - TimeDelta::days(1);
-            let mut new_day = self.get_day_tariffs(day_start, day_end, day_date_utc)?;
+            let mut new_day = self.get_day_prices(day_start, day_end, day_date_utc)?;
             new_day.append(&mut result);
             result = new_day;
         }
@@ -70,15 +65,15 @@ impl NordPool {
     /// * 'day_start' - the start time of the day to retrieve prices for
     /// * 'day_end' - the end time of the day to retrieve prices for (non-inclusive)
     /// * 'day_date' - the date to retrieve prices for
-    fn get_day_tariffs(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>, day_date: DateTime<Utc>) -> Result<Vec<TariffValue>, NordPoolError> {
+    fn get_day_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>, day_date: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, NordPoolError> {
         // https://dataportal-api.nordpoolgroup.com/api/DayAheadPrices?date=2025-10-22&market=DayAhead&deliveryArea=SE4&currency=SEK
         let url = "https://dataportal-api.nordpoolgroup.com/api/DayAheadPrices";
         let date = format!("{}", day_date.format("%Y-%m-%d"));
         let query = vec![
             ("date", date.as_str()),
             ("market", "DayAhead"),
-            ("deliveryArea", "SE4"),
-            ("currency", "SEK"),
+            ("deliveryArea", self.area.as_str()),
+            ("currency", self.currency.as_str()),
         ];
 
         let response = self.client
@@ -93,7 +88,7 @@ impl NordPool {
         let json = response.text()?;
 
         let tariffs: Tariffs = serde_json::from_str(&json)?;
-        let result = self.tariffs_to_vec(&tariffs, day_start, day_end)?;
+        let result = self.prices_to_vec(&tariffs, day_start, day_end)?;
         if result.is_empty() {
             Err(NordPoolError::NoContentError)? 
         } else {
@@ -106,57 +101,25 @@ impl NordPool {
     /// # Arguments
     ///
     /// * 'tariffs' - the struct containing prices
-    /// * 'day_start' - start of day to transform tariffs for
-    /// * 'day_end' - end of day to transform tariffs for (non-inclusive)
-    fn tariffs_to_vec(&self, tariffs: &Tariffs, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<TariffValue>, NordPoolError> {
+    /// * 'day_start' - start of day to transform prices for
+    /// * 'day_end' - end of day to transform prices for (non-inclusive)
+    fn prices_to_vec(&self, tariffs: &Tariffs, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, NordPoolError> {
         let entries = tariffs.multi_area_entries.len();
         if entries < 92 {
             return Err(NordPoolError::ContentLengthError)?
         }
-        let day_avg = tariffs.multi_area_entries.iter().map(|t| t.entry_per_area.se4).sum::<f64>() / entries as f64 / 1000.0;
+        let prices = tariffs.multi_area_entries.iter()
+            .map(|t| t.entry_per_area.get(&self.area)
+                .map(|p| (t.delivery_start, p / 1000.0)) // Currency per MWh to per kWh
+                .ok_or(NordPoolError::AreaError(format!("area {} missing in entry for {}", self.area, t.delivery_start))))
+            .collect::<Result<Vec<(DateTime<Utc>, f64)>, NordPoolError>>()?;
 
-        let mut result: Vec<TariffValue> = Vec::new();
-        tariffs.multi_area_entries.iter().filter(|t| t.delivery_start >= day_start && t.delivery_start < day_end).for_each(
-            |t| {
-                result.push(self.add_vat_markup(day_avg, t.entry_per_area.se4, t.delivery_start));
-            });
+        let result = prices.into_iter()
+            .filter(|(delivery_start, _)| *delivery_start >= day_start && *delivery_start < day_end)
+            .collect();
 
         Ok(result)
     }
-
-    /// Adds VAT and other markups such as energy taxes etc.
-    ///
-    /// # Arguments
-    ///
-    /// * 'day_avg' - average tariff for the day as from NordPool in SEK/MWh
-    /// * 'tariff' - spot fee as from NordPool in SEK/MWh
-    /// * 'delivery_start' - start time for the spot
-    fn add_vat_markup(&self, day_avg: f64, tariff: f64, delivery_start: DateTime<Utc>) -> TariffValue {
-        let price = tariff / 1000.0; // SEK per MWh to per kWh
-        let grid_fees = (self.variable_fee + self.energy_tax) / 100.0 + self.spot_fee_percentage * day_avg;
-        let trade_fees = (self.swedish_power_grid + self.balance_responsibility + self.electric_certificate +
-            self.guarantees_of_origin + self.fixed) / 100.0 + price;
-
-        let buy = (grid_fees + trade_fees) / 0.8;
-        let sell = self.production_price / 100.0 + price;
-
-        TariffValue {
-            valid_time: delivery_start,
-            price: round_to_two_decimals(price),
-            buy: round_to_two_decimals(buy),
-            sell: round_to_two_decimals(sell),
-        }
-    }
-}
-
-
-/// Rounds values to two decimals
-///
-/// # Arguments
-///
-/// * 'price' - the price to round to two decimals
-fn round_to_two_decimals(price: f64) -> f64 {
-    (price * 100f64).round() / 100f64
 }
 
 #[derive(Error, Debug)]
@@ -169,4 +132,6 @@ pub enum NordPoolError {
     NoContentError,
     #[error("ContentLengthError")]
     ContentLengthError,
+    #[error("AreaError: {0}")]
+    AreaError(String),
 }
\ No newline at end of file
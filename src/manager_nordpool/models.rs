@@ -1,18 +1,13 @@
+use std::collections::HashMap;
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
-#[derive(Deserialize, Debug)]
-pub struct EntryPerArea {
-    #[serde(rename = "SE4")]
-    pub se4: f64,
-}
-
 #[derive(Deserialize, Debug)]
 pub struct MultiAreaEntries {
     #[serde(rename = "deliveryStart")]
     pub delivery_start: DateTime<Utc>,
     #[serde(rename = "entryPerArea")]
-    pub entry_per_area: EntryPerArea,
+    pub entry_per_area: HashMap<String, f64>,
 }
 
 #[derive(Deserialize, Debug)]
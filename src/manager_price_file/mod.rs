@@ -0,0 +1,229 @@
+use std::fs;
+use std::path::Path;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Deserialize;
+use anyhow::Result;
+use thiserror::Error;
+use crate::config::Config;
+
+/// One entry in a JSON price file
+#[derive(Deserialize)]
+struct PriceEntry {
+    timestamp: DateTime<Utc>,
+    spot: f64,
+}
+
+/// Reads hand-crafted spot prices from a CSV or JSON file, e.g. for offline or what-if runs
+pub struct PriceFile {
+    path: String,
+}
+
+impl PriceFile {
+    pub fn new(config: &Config) -> Result<PriceFile, PriceFileError> {
+        let path = config.prices.file.clone()
+            .ok_or(PriceFileError::ConfigError("file price provider selected but no price file configured".to_string()))?;
+
+        Ok(Self { path })
+    }
+
+    /// Reads spot prices (SEK/kWh) per quarter from the price file. The file is read on each
+    /// call so that it can be edited between runs. Timestamps must be aligned to whole quarters
+    /// and the prices must cover the whole requested period.
+    ///
+    /// # Arguments
+    ///
+    /// * 'day_start' - the start time of the period to retrieve prices for
+    /// * 'day_end' - the end time of the period to retrieve prices for (non-inclusive)
+    pub fn get_spot_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, PriceFileError> {
+        let content = fs::read_to_string(&self.path)?;
+
+        let mut entries = if Path::new(&self.path).extension().is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+            serde_json::from_str::<Vec<PriceEntry>>(&content)?
+        } else {
+            parse_csv(&content)?
+        };
+        entries.sort_by_key(|e| e.timestamp);
+        if let Some(entry) = entries.iter().find(|e| e.timestamp.timestamp() % 900 != 0) {
+            return Err(PriceFileError::AlignmentError(format!("{}: timestamp {} is not aligned to 15 minutes", self.path, entry.timestamp)));
+        }
+
+        let mut result = entries_to_quarters(&entries);
+        result.retain(|(time, _)| *time >= day_start && *time < day_end);
+
+        let quarters = (day_end - day_start).num_minutes() as usize / 15;
+        if result.is_empty() {
+            Err(PriceFileError::NoContentError(format!("no prices in {} for requested period", self.path)))
+        } else if result.len() < quarters {
+            Err(PriceFileError::CoverageError(format!("{} covers {} of {} quarters from {} to {}",
+                self.path, result.len(), quarters, day_start, day_end)))
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+/// Parses CSV price file content with lines of timestamp (RFC 3339) and spot price (SEK/MWh).
+/// A header line, empty lines and lines starting with # are skipped.
+///
+/// # Arguments
+///
+/// * 'content' - the file content
+fn parse_csv(content: &str) -> Result<Vec<PriceEntry>, PriceFileError> {
+    let mut entries: Vec<PriceEntry> = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || (i == 0 && line.starts_with("timestamp")) {
+            continue;
+        }
+
+        let (timestamp, spot) = line.split_once([',', ';'])
+            .ok_or(PriceFileError::ParseError(format!("line {}: expected timestamp and spot price", i + 1)))?;
+        let timestamp = DateTime::parse_from_rfc3339(timestamp.trim())
+            .map_err(|e| PriceFileError::ParseError(format!("line {}: {}", i + 1, e)))?
+            .with_timezone(&Utc);
+        let spot = spot.trim().parse::<f64>()
+            .map_err(|e| PriceFileError::ParseError(format!("line {}: {}", i + 1, e)))?;
+
+        entries.push(PriceEntry { timestamp, spot });
+    }
+
+    Ok(entries)
+}
+
+/// Expands price entries to quarterly prices in SEK/kWh. Each entry is valid until the next
+/// entry, so hourly and quarterly prices can be mixed. The last entry is given the same length
+/// as the one before it.
+///
+/// # Arguments
+///
+/// * 'entries' - price entries ordered by time
+fn entries_to_quarters(entries: &[PriceEntry]) -> Vec<(DateTime<Utc>, f64)> {
+    let mut result: Vec<(DateTime<Utc>, f64)> = Vec::new();
+    let mut length = TimeDelta::minutes(15);
+
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(next) = entries.get(i + 1) {
+            length = next.timestamp - entry.timestamp;
+        }
+
+        let mut time = entry.timestamp;
+        while time < entry.timestamp + length {
+            result.push((time, entry.spot / 1000.0)); // SEK per MWh to per kWh
+            time += TimeDelta::minutes(15);
+        }
+    }
+
+    result
+}
+
+#[derive(Error, Debug)]
+pub enum PriceFileError {
+    #[error("ConfigError: {0}")]
+    ConfigError(String),
+    #[error("FileError: {0}")]
+    FileError(#[from] std::io::Error),
+    #[error("DocumentError: {0}")]
+    DocumentError(#[from] serde_json::Error),
+    #[error("ParseError: {0}")]
+    ParseError(String),
+    #[error("AlignmentError: {0}")]
+    AlignmentError(String),
+    #[error("CoverageError: {0}")]
+    CoverageError(String),
+    #[error("NoContentError: {0}")]
+    NoContentError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+
+    fn price_file(name: &str, content: &str) -> (PriceFile, PathBuf) {
+        let path = std::env::temp_dir().join(format!("mygrid_price_file_{}_{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        (PriceFile { path: format!("{}", path.display()) }, path)
+    }
+
+    fn entry(time: &str, spot: f64) -> PriceEntry {
+        PriceEntry { timestamp: utc(time), spot }
+    }
+
+    #[test]
+    fn parses_csv_skipping_header_and_comments() {
+        let content = "timestamp,spot\n# what-if\n\n2025-10-22T00:00:00+02:00,412.5\n2025-10-21T22:15:00Z; -3.25\n";
+
+        let entries = parse_csv(content).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].timestamp, entries[0].spot), (utc("2025-10-21T22:00:00Z"), 412.5));
+        assert_eq!((entries[1].timestamp, entries[1].spot), (utc("2025-10-21T22:15:00Z"), -3.25));
+    }
+
+    #[test]
+    fn reports_the_csv_line_of_a_bad_entry() {
+        let result = parse_csv("2025-10-21T22:00:00Z,412.5\n2025-10-21T22:15:00Z,n/a\n");
+
+        assert!(matches!(result, Err(PriceFileError::ParseError(e)) if e.starts_with("line 2:")));
+    }
+
+    #[test]
+    fn reads_json_and_csv_files() {
+        let (json, json_path) = price_file("prices.json", r#"[
+            {"timestamp": "2025-10-21T23:00:00Z", "spot": 200.0},
+            {"timestamp": "2025-10-21T22:00:00Z", "spot": 100.0}
+        ]"#);
+        let (csv, csv_path) = price_file("prices.csv", "2025-10-21T22:00:00Z,100.0\n2025-10-21T23:00:00Z,200.0\n");
+
+        let from_json = json.get_spot_prices(utc("2025-10-21T22:00:00Z"), utc("2025-10-22T00:00:00Z"));
+        let from_csv = csv.get_spot_prices(utc("2025-10-21T22:00:00Z"), utc("2025-10-22T00:00:00Z"));
+        fs::remove_file(json_path).unwrap();
+        fs::remove_file(csv_path).unwrap();
+
+        let from_json = from_json.unwrap();
+        assert_eq!(from_json.len(), 8);
+        assert_eq!(from_json[3], (utc("2025-10-21T22:45:00Z"), 0.1));
+        assert_eq!(from_json[4], (utc("2025-10-21T23:00:00Z"), 0.2));
+        assert_eq!(from_json, from_csv.unwrap());
+    }
+
+    #[test]
+    fn last_entry_gets_the_length_of_the_one_before() {
+        let hourly = entries_to_quarters(&[entry("2025-10-21T22:00:00Z", 100.0), entry("2025-10-21T23:00:00Z", 200.0)]);
+        let quarterly = entries_to_quarters(&[entry("2025-10-21T22:00:00Z", 100.0), entry("2025-10-21T23:00:00Z", 200.0), entry("2025-10-21T23:15:00Z", 300.0)]);
+        let single = entries_to_quarters(&[entry("2025-10-21T22:00:00Z", 100.0)]);
+
+        assert_eq!(hourly.len(), 8);
+        assert_eq!(hourly.last().unwrap(), &(utc("2025-10-21T23:45:00Z"), 0.2));
+        assert_eq!(quarterly.len(), 6);
+        assert_eq!(quarterly.last().unwrap(), &(utc("2025-10-21T23:15:00Z"), 0.3));
+        assert_eq!(single, vec![(utc("2025-10-21T22:00:00Z"), 0.1)]);
+    }
+
+    #[test]
+    fn rejects_timestamps_not_aligned_to_quarters() {
+        let (file, path) = price_file("unaligned.csv", "2025-10-21T22:00:00Z,100.0\n2025-10-21T22:10:00Z,200.0\n");
+
+        let result = file.get_spot_prices(utc("2025-10-21T22:00:00Z"), utc("2025-10-21T23:00:00Z"));
+        fs::remove_file(path).unwrap();
+
+        assert!(matches!(result, Err(PriceFileError::AlignmentError(_))));
+    }
+
+    #[test]
+    fn rejects_files_not_covering_the_period() {
+        let (file, path) = price_file("partial.csv", "2025-10-21T22:00:00Z,100.0\n2025-10-21T23:00:00Z,200.0\n");
+
+        let partial = file.get_spot_prices(utc("2025-10-21T22:00:00Z"), utc("2025-10-22T22:00:00Z"));
+        let outside = file.get_spot_prices(utc("2025-10-23T22:00:00Z"), utc("2025-10-24T22:00:00Z"));
+        fs::remove_file(path).unwrap();
+
+        assert!(matches!(partial, Err(PriceFileError::CoverageError(e)) if e.contains("8 of 96 quarters")));
+        assert!(matches!(outside, Err(PriceFileError::NoContentError(_))));
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use chrono::{DateTime, DurationRound, Local, NaiveDate, TimeDelta, Utc};
+use crate::manager_prices::Prices;
+use crate::models::{ForecastValues, TariffValue};
+
+impl Prices {
+    /// Returns the number of hours the schedule may be extended beyond published prices by
+    /// estimated prices, 0 if price forecasting is not configured
+    pub fn forecast_hours(&self) -> i64 {
+        self.forecast.as_ref().map(|f| f.hours).unwrap_or(0)
+    }
+
+    /// Extends tariffs with estimated prices up to the given end time. Each estimated spot
+    /// price blends the average spot price at the same weekday and local time in the price
+    /// cache with the last known price at the same time of day, adjusted by how much colder or
+    /// warmer the weather forecast is compared to the period with known prices. Estimated
+    /// tariffs are flagged as such.
+    ///
+    /// # Arguments
+    ///
+    /// * 'tariffs' - known tariffs, ordered by time
+    /// * 'end' - the time to extend tariffs to (non-inclusive)
+    /// * 'weather' - weather forecast covering the estimated period
+    pub fn extend_with_forecast(&self, mut tariffs: Vec<TariffValue>, end: DateTime<Utc>, weather: &ForecastValues) -> Vec<TariffValue> {
+        let (Some(params), Some(last)) = (self.forecast.as_ref(), tariffs.last()) else {
+            return tariffs;
+        };
+        let last_known = last.valid_time;
+        let start = last_known + TimeDelta::minutes(15);
+        if start >= end {
+            return tariffs;
+        }
+
+        let known: HashMap<DateTime<Utc>, f64> = tariffs.iter().map(|t| (t.valid_time, t.price)).collect();
+        let temperatures: HashMap<DateTime<Utc>, f64> = weather.forecast.iter().map(|f| (f.valid_time, f.temp)).collect();
+        let known_temps: Vec<f64> = weather.forecast.iter()
+            .filter(|f| f.valid_time <= last_known)
+            .map(|f| f.temp)
+            .collect();
+        let known_temp = (!known_temps.is_empty()).then(|| known_temps.iter().sum::<f64>() / known_temps.len() as f64);
+
+        let mut history: HashMap<NaiveDate, HashMap<DateTime<Utc>, f64>> = HashMap::new();
+        let mut prices: Vec<(DateTime<Utc>, f64)> = tariffs.iter().map(|t| (t.valid_time, t.price)).collect();
+        let mut time = start;
+        while time < end {
+            let history_prices: Vec<f64> = (1..=params.history_weeks)
+                .filter_map(|w| {
+                    let then = (time.with_timezone(&Local).naive_local() - TimeDelta::weeks(w))
+                        .and_local_timezone(Local)
+                        .earliest()?
+                        .with_timezone(&Utc);
+                    history
+                        .entry(then.with_timezone(&Local).date_naive())
+                        .or_insert_with_key(|date| self.cached_spot_prices(*date)
+                            .map(|day| day.into_iter().collect())
+                            .unwrap_or_default())
+                        .get(&then)
+                        .copied()
+                })
+                .collect();
+            let persistence = (1..=3)
+                .find_map(|d| known.get(&(time - TimeDelta::days(d))).copied())
+                .unwrap_or(last.price);
+
+            let mut spot = if history_prices.is_empty() {
+                persistence
+            } else {
+                let history_avg = history_prices.iter().sum::<f64>() / history_prices.len() as f64;
+                params.history_weight * history_avg + (1.0 - params.history_weight) * persistence
+            };
+
+            let hour = time.duration_trunc(TimeDelta::hours(1)).unwrap_or(time);
+            if let (Some(known_temp), Some(temp)) = (known_temp, temperatures.get(&hour)) {
+                spot += params.temperature_coefficient * (known_temp - temp);
+            }
+
+            prices.push((time, spot));
+            time += TimeDelta::minutes(15);
+        }
+
+        let mut estimated = self.formula.tariff_values(prices, start, end);
+        estimated.iter_mut().for_each(|t| t.estimated = true);
+        tariffs.append(&mut estimated);
+
+        tariffs
+    }
+}
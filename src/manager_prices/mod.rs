@@ -0,0 +1,427 @@
+use std::fs;
+use std::path::PathBuf;
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use thiserror::Error;
+use crate::config::{Config, PriceProvider};
+use crate::manager_entsoe::{Entsoe, EntsoeError};
+use crate::manager_nordpool::{NordPool, NordPoolError};
+use crate::manager_price_file::{PriceFile, PriceFileError};
+use crate::models::TariffValue;
+use crate::config::PriceForecastParameters;
+use crate::tariff_formula::PriceFormula;
+
+mod forecast;
+
+/// A source of day ahead spot prices, fees and VAT are applied by the price formula in Prices
+pub trait TariffProvider {
+    /// Returns the name of the provider, used for logging
+    fn name(&self) -> &'static str;
+
+    /// Returns the key prices from the provider are cached by, i.e. the delivery area or bidding
+    /// zone and the currency they are given for, or None if prices from the provider should not be cached
+    fn cache_key(&self) -> Option<String> {
+        None
+    }
+
+    /// Retrieves spot prices (currency/kWh) per quarter for the given period
+    ///
+    /// # Arguments
+    ///
+    /// * 'day_start' - the start time of the period to retrieve prices for
+    /// * 'day_end' - the end time of the period to retrieve prices for (non-inclusive)
+    fn get_spot_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, PricesError>;
+}
+
+impl TariffProvider for NordPool {
+    fn name(&self) -> &'static str {
+        "NordPool"
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        Some(format!("{}_{}", self.area(), self.currency()))
+    }
+
+    fn get_spot_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, PricesError> {
+        Ok(NordPool::get_spot_prices(self, day_start, day_end)?)
+    }
+}
+
+impl TariffProvider for Entsoe {
+    fn name(&self) -> &'static str {
+        "ENTSO-E"
+    }
+
+    fn cache_key(&self) -> Option<String> {
+        Some(format!("{}_{}", self.domain(), self.currency()))
+    }
+
+    fn get_spot_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, PricesError> {
+        Ok(Entsoe::get_spot_prices(self, day_start, day_end)?)
+    }
+}
+
+impl TariffProvider for PriceFile {
+    fn name(&self) -> &'static str {
+        "price file"
+    }
+
+    fn get_spot_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, PricesError> {
+        Ok(PriceFile::get_spot_prices(self, day_start, day_end)?)
+    }
+}
+
+/// A cached spot price (currency/kWh)
+#[derive(Serialize, Deserialize)]
+struct CachedPrice {
+    valid_time: DateTime<Utc>,
+    spot: f64,
+}
+
+/// Retrieves prices day by day from the configured providers in priority order, where the
+/// on-disk cache of spot prices is used in place of cacheable providers if the day has been
+/// fetched before. Fees and VAT are applied to spot prices whether fetched or cached.
+pub struct Prices {
+    providers: Vec<Box<dyn TariffProvider>>,
+    cache_dir: Option<String>,
+    forecast: Option<PriceForecastParameters>,
+    formula: PriceFormula,
+}
+
+impl Prices {
+    pub fn new(config: &Config) -> Result<Prices, PricesError> {
+        let mut providers: Vec<Box<dyn TariffProvider>> = Vec::new();
+        for provider in config.prices.providers.iter() {
+            match provider {
+                PriceProvider::NordPool => providers.push(Box::new(NordPool::new(config)?)),
+                PriceProvider::Entsoe => providers.push(Box::new(Entsoe::new(config)?)),
+                PriceProvider::File => providers.push(Box::new(PriceFile::new(config)?)),
+            }
+        }
+
+        if providers.is_empty() {
+            return Err(PricesError::ConfigError("no price providers configured".to_string()));
+        }
+
+        Ok(Self {
+            providers,
+            cache_dir: config.prices.cache_dir.clone(),
+            forecast: config.prices.forecast.clone(),
+            formula: PriceFormula::new(&config.tariff_fees, &config.tariff_formula),
+        })
+    }
+
+    /// Retrieves prices for all local days touched by the given period and returns those
+    /// within the period
+    ///
+    /// # Arguments
+    ///
+    /// * 'day_start' - the start time of the period to retrieve prices for
+    /// * 'day_end' - the end time of the period to retrieve prices for (non-inclusive)
+    pub fn get_tariffs(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<TariffValue>, PricesError> {
+        let mut date = day_start.with_timezone(&Local).date_naive();
+        let last_date = (day_end - TimeDelta::minutes(1)).with_timezone(&Local).date_naive();
+
+        let mut result: Vec<TariffValue> = Vec::new();
+        while date <= last_date {
+            result.append(&mut self.get_day_tariffs(date)?);
+            date = date.succ_opt().unwrap();
+        }
+        result.retain(|t| t.valid_time >= day_start && t.valid_time < day_end);
+
+        Ok(result)
+    }
+
+    /// Retrieves prices for a whole local day from the first provider that succeeds. Before a
+    /// cacheable provider is tried the cache is checked for its cache key, and fetched spot prices
+    /// from cacheable providers are saved to cache.
+    ///
+    /// # Arguments
+    ///
+    /// * 'date' - the local date to retrieve prices for
+    fn get_day_tariffs(&self, date: NaiveDate) -> Result<Vec<TariffValue>, PricesError> {
+        let (start, end) = local_day(date)?;
+        let mut errors: Vec<String> = Vec::new();
+        for provider in self.providers.iter() {
+            if let Some(cached) = provider.cache_key().and_then(|key| self.load_cache(&key, date)) {
+                info!("prices for {} loaded from cache", date);
+                return Ok(self.formula.tariff_values(cached, start, end));
+            }
+
+            match provider.get_spot_prices(start, end) {
+                Ok(prices) => {
+                    if errors.is_empty() {
+                        info!("prices for {} retrieved from {}", date, provider.name());
+                    } else {
+                        warn!("prices for {} retrieved from fallback provider {}", date, provider.name());
+                    }
+                    if let Some(key) = provider.cache_key() {
+                        self.save_cache(&key, date, &prices);
+                    }
+                    return Ok(self.formula.tariff_values(prices, start, end));
+                },
+                Err(e) => {
+                    warn!("failed to get prices for {} from {}: {}", date, provider.name(), e);
+                    errors.push(format!("{}: {}", provider.name(), e));
+                },
+            }
+        }
+
+        Err(PricesError::NoContentError(errors.join(", ")))
+    }
+
+    /// Returns cached spot prices for a date from the first cacheable provider that has them
+    ///
+    /// # Arguments
+    ///
+    /// * 'date' - the local date of the prices
+    fn cached_spot_prices(&self, date: NaiveDate) -> Option<Vec<(DateTime<Utc>, f64)>> {
+        self.providers.iter()
+            .filter_map(|p| p.cache_key())
+            .find_map(|key| self.load_cache(&key, date))
+    }
+
+    /// Returns the cache file path for a cache key and date, if caching is configured
+    ///
+    /// # Arguments
+    ///
+    /// * 'key' - the cache key of the provider, i.e. area or bidding zone and currency
+    /// * 'date' - the local date of the prices
+    fn cache_path(&self, key: &str, date: NaiveDate) -> Option<PathBuf> {
+        self.cache_dir.as_ref()
+            .map(|dir| PathBuf::from(dir).join(format!("{}_{}.json", key, date.format("%Y-%m-%d"))))
+    }
+
+    /// Loads cached spot prices for a cache key and date, a missing or unreadable cache file gives None
+    ///
+    /// # Arguments
+    ///
+    /// * 'key' - the cache key of the provider, i.e. area or bidding zone and currency
+    /// * 'date' - the local date of the prices
+    fn load_cache(&self, key: &str, date: NaiveDate) -> Option<Vec<(DateTime<Utc>, f64)>> {
+        let path = self.cache_path(key, date)?;
+        let json = fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<Vec<CachedPrice>>(&json) {
+            Ok(prices) if !prices.is_empty() => Some(prices.into_iter().map(|p| (p.valid_time, p.spot)).collect()),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("ignoring unreadable price cache {}: {}", path.display(), e);
+                None
+            },
+        }
+    }
+
+    /// Saves spot prices for a cache key and date to cache. Failing to save is not an error since
+    /// prices can always be fetched again.
+    ///
+    /// # Arguments
+    ///
+    /// * 'key' - the cache key of the provider, i.e. area or bidding zone and currency
+    /// * 'date' - the local date of the prices
+    /// * 'prices' - the spot prices to save
+    fn save_cache(&self, key: &str, date: NaiveDate, prices: &[(DateTime<Utc>, f64)]) {
+        if let Some(path) = self.cache_path(key, date) {
+            let cached: Vec<CachedPrice> = prices.iter().map(|&(valid_time, spot)| CachedPrice { valid_time, spot }).collect();
+            let result = serde_json::to_string_pretty(&cached)
+                .map_err(|e| e.to_string())
+                .and_then(|json| fs::write(&path, json).map_err(|e| e.to_string()));
+            if let Err(e) = result {
+                warn!("failed to save price cache {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Returns the start and end (non-inclusive) in UTC of a local date
+///
+/// # Arguments
+///
+/// * 'date' - the local date
+fn local_day(date: NaiveDate) -> Result<(DateTime<Utc>, DateTime<Utc>), PricesError> {
+    let start_of = |d: NaiveDate| d.and_hms_opt(0, 0, 0).unwrap()
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|d| d.with_timezone(&Utc))
+        .ok_or(PricesError::DateError(format!("no local midnight for {}", d)));
+
+    Ok((start_of(date)?, start_of(date.succ_opt().unwrap())?))
+}
+
+/// Error depicting errors that occur while retrieving prices
+///
+#[derive(Debug, Error)]
+pub enum PricesError {
+    #[error("ConfigError: {0}")]
+    ConfigError(String),
+    #[error("DateError: {0}")]
+    DateError(String),
+    #[error("NordPoolError: {0}")]
+    NordPoolError(#[from] NordPoolError),
+    #[error("EntsoeError: {0}")]
+    EntsoeError(#[from] EntsoeError),
+    #[error("PriceFileError: {0}")]
+    PriceFileError(#[from] PriceFileError),
+    #[error("NoContentError: {0}")]
+    NoContentError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use crate::config::TariffFees;
+    use super::*;
+
+    /// Provider giving a flat spot price, or failing if no price is given, and counting its fetches
+    struct MockProvider {
+        key: Option<&'static str>,
+        spot: Option<f64>,
+        fetches: Rc<Cell<usize>>,
+    }
+
+    impl TariffProvider for MockProvider {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        fn cache_key(&self) -> Option<String> {
+            self.key.map(|k| k.to_string())
+        }
+
+        fn get_spot_prices(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<(DateTime<Utc>, f64)>, PricesError> {
+            self.fetches.set(self.fetches.get() + 1);
+            let spot = self.spot.ok_or(PricesError::NoContentError("mock failure".to_string()))?;
+            let quarters = (day_end - day_start).num_minutes() / 15;
+
+            Ok((0..quarters).map(|q| (day_start + TimeDelta::minutes(q * 15), spot)).collect())
+        }
+    }
+
+    fn provider(key: Option<&'static str>, spot: Option<f64>) -> (Box<dyn TariffProvider>, Rc<Cell<usize>>) {
+        let fetches = Rc::new(Cell::new(0));
+        (Box::new(MockProvider { key, spot, fetches: fetches.clone() }), fetches)
+    }
+
+    fn prices(providers: Vec<Box<dyn TariffProvider>>, cache_dir: &std::path::Path) -> Prices {
+        let fees: TariffFees = toml::from_str(r#"
+            variable_fee = 25.30
+            spot_fee_percentage = 7.7
+            energy_tax = 36.0
+            swedish_power_grid = 1.833
+            balance_responsibility = 0.80
+            electric_certificate = 1.60
+            guarantees_of_origin = 3.00
+            fixed = 2.40
+            production_price = 7.5
+        "#).unwrap();
+
+        Prices {
+            providers,
+            cache_dir: Some(format!("{}", cache_dir.display())),
+            forecast: None,
+            formula: PriceFormula::new(&fees, &None),
+        }
+    }
+
+    fn cache_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mygrid_prices_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 10, 22).unwrap()
+    }
+
+    #[test]
+    fn falls_back_to_providers_in_priority_order() {
+        let dir = cache_dir("fallback");
+        let (failing, failing_fetches) = provider(Some("SE4_SEK"), None);
+        let (first, first_fetches) = provider(None, Some(0.5));
+        let (second, second_fetches) = provider(None, Some(0.9));
+
+        let tariffs = prices(vec![failing, first, second], &dir).get_day_tariffs(date());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let tariffs = tariffs.unwrap();
+        assert!(!tariffs.is_empty() && tariffs.iter().all(|t| t.price == 0.5));
+        assert_eq!((failing_fetches.get(), first_fetches.get(), second_fetches.get()), (1, 1, 0));
+    }
+
+    #[test]
+    fn fails_when_all_providers_fail() {
+        let dir = cache_dir("failing");
+        let (first, _) = provider(None, None);
+        let (second, second_fetches) = provider(Some("SE4_SEK"), None);
+
+        let tariffs = prices(vec![first, second], &dir).get_day_tariffs(date());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(tariffs, Err(PricesError::NoContentError(_))));
+        assert_eq!(second_fetches.get(), 1);
+    }
+
+    #[test]
+    fn cached_prices_are_used_instead_of_fetching() {
+        let dir = cache_dir("hit");
+        let (cacheable, fetches) = provider(Some("SE4_SEK"), Some(0.5));
+        let prices = prices(vec![cacheable], &dir);
+
+        let fetched = prices.get_day_tariffs(date()).unwrap();
+        let cached = prices.get_day_tariffs(date()).unwrap();
+        let cache_file = dir.join("SE4_SEK_2025-10-22.json").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(cache_file);
+        assert_eq!(fetches.get(), 1);
+        assert_eq!(cached.len(), fetched.len());
+        assert!(cached.iter().zip(fetched.iter()).all(|(c, f)| c.valid_time == f.valid_time && c.buy == f.buy));
+    }
+
+    #[test]
+    fn cache_of_a_lower_priority_provider_is_used_before_fetching_it() {
+        let dir = cache_dir("order");
+        let (cacheable, _) = provider(Some("SE4_SEK"), Some(0.5));
+        prices(vec![cacheable], &dir).get_day_tariffs(date()).unwrap();
+
+        let (failing, failing_fetches) = provider(None, None);
+        let (cacheable, fetches) = provider(Some("SE4_SEK"), Some(0.9));
+        let tariffs = prices(vec![failing, cacheable], &dir).get_day_tariffs(date());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(tariffs.unwrap().iter().all(|t| t.price == 0.5));
+        assert_eq!((failing_fetches.get(), fetches.get()), (1, 0));
+    }
+
+    #[test]
+    fn prices_in_another_currency_are_not_taken_from_cache() {
+        let dir = cache_dir("currency");
+        let (sek, _) = provider(Some("SE4_SEK"), Some(0.5));
+        prices(vec![sek], &dir).get_day_tariffs(date()).unwrap();
+
+        let (eur, fetches) = provider(Some("SE4_EUR"), Some(0.05));
+        let tariffs = prices(vec![eur], &dir).get_day_tariffs(date());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(tariffs.unwrap().iter().all(|t| t.price == 0.05));
+        assert_eq!(fetches.get(), 1);
+    }
+
+    #[test]
+    fn prices_from_providers_without_cache_key_are_not_cached() {
+        let dir = cache_dir("uncached");
+        let (file, fetches) = provider(None, Some(0.5));
+        let prices = prices(vec![file], &dir);
+
+        prices.get_day_tariffs(date()).unwrap();
+        prices.get_day_tariffs(date()).unwrap();
+        let cache_files = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(fetches.get(), 2);
+        assert_eq!(cache_files, 0);
+    }
+}
@@ -0,0 +1,50 @@
+use crate::config::InverterParameters;
+use crate::spline::MonotonicCubicSpline;
+
+/// Converts DC power from the PV arrays to AC power given the inverter's efficiency and AC max
+/// power. Any grid export limit is left to the scheduler since surplus PV can still charge the battery.
+///
+pub struct Inverter {
+    ac_max_power: f64,
+    efficiency: MonotonicCubicSpline,
+}
+
+impl Inverter {
+    /// Returns a new instance of Inverter
+    ///
+    /// # Arguments
+    ///
+    /// * 'params' - inverter parameters
+    pub fn new(params: &InverterParameters) -> Inverter {
+        let (curve_x, curve_y): (Vec<f64>, Vec<f64>) = params.efficiency_curve
+            .iter()
+            .map(|c| (c.0, c.1))
+            .unzip();
+
+        Inverter {
+            ac_max_power: params.ac_max_power,
+            efficiency: MonotonicCubicSpline::new(&curve_x, &curve_y)
+                .expect("inverter efficiency curve should be checked when loading configuration"),
+        }
+    }
+
+    /// Returns AC power and clipped power per minute. The DC power is converted with the
+    /// efficiency given by its load fraction of AC max power, and the AC power is then limited to
+    /// AC max power. Whatever is cut off by the limit is returned as clipped power.
+    ///
+    /// # Arguments
+    ///
+    /// * 'dc_power' - DC power from the PV arrays per minute
+    pub fn ac_power(&self, dc_power: &[f64]) -> (Vec<f64>, Vec<f64>) {
+        dc_power.iter()
+            .map(|dc| {
+                if *dc <= 0.0 {
+                    return (0.0, 0.0);
+                }
+                let ac = dc * self.efficiency.interpolate(dc / self.ac_max_power).clamp(0.0, 1.0);
+
+                (ac.min(self.ac_max_power), (ac - self.ac_max_power).max(0.0))
+            })
+            .unzip()
+    }
+}
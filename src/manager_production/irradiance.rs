@@ -0,0 +1,109 @@
+use std::f64::consts::PI;
+use chrono::{DateTime, Datelike, TimeDelta, Utc};
+use crate::manager_production::{schlick_iam, shading, PVProduction, ProductionError};
+
+/// Irradiance (W/m2) at standard test conditions, i.e. where panels give their rated power
+const STC_IRRADIANCE: f64 = 1000.0;
+
+/// Solar constant (W/m2)
+const SOLAR_CONSTANT: f64 = 1367.0;
+
+impl PVProduction {
+    /// Calculates estimated power per minute from forecast irradiance. Global, direct normal
+    /// and diffuse horizontal irradiance are transposed onto each panel plane using the
+    /// Hay-Davies model, with beam and circumsolar irradiance reduced by nearby obstacles and
+    /// beam irradiance by the incidence angle modifier.
+    ///
+    /// # Arguments
+    ///
+    /// * 'day_start' - the start time of the day to calculate for
+    /// * 'day_end' - the end time of the day to calculate for (non-inclusive)
+    /// * 'temp' - ambient temperature in degrees Celsius per minute
+    /// * 'ghi' - global horizontal irradiance (W/m2) per minute
+    /// * 'dni' - direct normal irradiance (W/m2) per minute
+    /// * 'dhi' - diffuse horizontal irradiance (W/m2) per minute
+    pub(super) fn irradiance_power(&self, day_start: DateTime<Utc>, day_end: DateTime<Utc>, temp: &[f64], ghi: &[f64], dni: &[f64], dhi: &[f64]) -> Result<Vec<f64>, ProductionError> {
+        let minutes = (day_end - day_start).num_minutes() as usize;
+        let sp = self.solar_positions(day_start, day_end)?;
+        let mut power: Vec<f64> = vec![0.0; minutes];
+
+        for (array, incidence) in self.arrays.iter().zip(sp.incidence.iter()) {
+            let (up_down, direct) = shading(array, &sp);
+
+            if sp.rise_set.len() != up_down.len() {
+                return Err(ProductionError::UnequalLengths("between rise_set and up_down vectors".to_string()));
+            }
+
+            let mut poa: Vec<f64> = vec![0.0; minutes];
+            sp.rise_set.iter().for_each(|(sunrise, sunset)| {
+                for minute_of_day in *sunrise..*sunset {
+                    let date_time = day_start + TimeDelta::minutes(minute_of_day as i64);
+                    let sky = Sky {
+                        zenith: sp.zenith[minute_of_day],
+                        ghi: ghi[minute_of_day].max(0.0),
+                        dni: dni[minute_of_day].max(0.0),
+                        dhi: dhi[minute_of_day].max(0.0),
+                        extraterrestrial: extraterrestrial_irradiance(date_time),
+                    };
+
+                    poa[minute_of_day] = self.plane_of_array(&sky, array.tilt, incidence[minute_of_day], direct[minute_of_day]);
+                }
+            });
+
+            // Heating by the sun is given by plane of array irradiance relative to standard test conditions
+            let normal_incidence = vec![0.0; minutes];
+            let sun: Vec<f64> = poa.iter().map(|p| p / STC_IRRADIANCE).collect();
+            let roof_temperature = self.roof_temperature(&up_down, temp, &normal_incidence, &sun)?;
+
+            for m in 0..minutes {
+                let temp_red = 1.0 - (roof_temperature[m].max(0.0) - 25.0) * self.panel_temp_red / 100.0;
+                power[m] += array.panel_power * array.panels as f64 * sun[m] * temp_red;
+            }
+        }
+
+        Ok(power)
+    }
+
+    /// Returns the effective irradiance (W/m2) on a panel plane using the Hay-Davies model
+    ///
+    /// # Arguments
+    ///
+    /// * 'sky' - sun position and irradiance components
+    /// * 'tilt' - panel plane tilt from horizontal in degrees
+    /// * 'incidence' - sun incidence angle on the panel plane in degrees
+    /// * 'shadow' - factor (0-1) for how much of the sun is free from nearby obstacles
+    fn plane_of_array(&self, sky: &Sky, tilt: f64, incidence: f64, shadow: f64) -> f64 {
+        let cos_incidence = incidence.to_radians().cos().max(0.0);
+        let cos_zenith = sky.zenith.to_radians().cos().max(0.087); // Limit at 85 degrees to avoid blow up near the horizon
+        let cos_slope = tilt.to_radians().cos();
+
+        let anisotropy = (sky.dni / sky.extraterrestrial).clamp(0.0, 1.0);
+        let rb = cos_incidence / cos_zenith;
+
+        let beam = sky.dni * cos_incidence * schlick_iam(incidence, self.iam_factor) * shadow;
+        let circumsolar = sky.dhi * anisotropy * rb * shadow;
+        let isotropic = sky.dhi * (1.0 - anisotropy) * (1.0 + cos_slope) / 2.0;
+        let ground = sky.ghi * self.albedo * (1.0 - cos_slope) / 2.0;
+
+        beam + circumsolar + isotropic + ground
+    }
+}
+
+/// Sun position and irradiance components for one point in time
+struct Sky {
+    zenith: f64,
+    ghi: f64,
+    dni: f64,
+    dhi: f64,
+    extraterrestrial: f64,
+}
+
+/// Returns the extraterrestrial normal irradiance (W/m2), which varies over the year with the
+/// distance between the earth and the sun
+///
+/// # Arguments
+///
+/// * 'date_time' - the time to calculate for
+fn extraterrestrial_irradiance(date_time: DateTime<Utc>) -> f64 {
+    SOLAR_CONSTANT * (1.0 + 0.033 * (2.0 * PI * date_time.ordinal() as f64 / 365.0).cos())
+}
@@ -1,28 +1,31 @@
+mod inverter;
+mod irradiance;
+
 use std::ops::Add;
 use chrono::{DateTime, Datelike, DurationRound, TimeDelta, Utc};
 use anyhow::Result;
 use spa_sra::errors::SpaError;
 use spa_sra::spa::{Function, Input, SpaData};
 use thiserror::Error;
-use crate::config::ProductionParameters;
+use crate::config::{PVArray, ProductionModel, ProductionParameters};
+use crate::manager_production::inverter::Inverter;
 use crate::models::{ForecastValues, ForecastValuesError};
 
 
 /// Struct for calculating PV production based on solar positions and cloud conditions
 ///
 pub struct PVProduction {
+    model: ProductionModel,
+    albedo: f64,
     lat: f64,
     long: f64,
-    panel_power: f64,
-    panel_slope: f64,
-    panel_east_azm: f64,
+    arrays: Vec<PVArray>,
+    inverter: Option<Inverter>,
     panel_temp_red: f64,
     tau: f64,
     tau_down: f64,
     k_gain: f64,
     iam_factor: f64,
-    start_azm_elv: Vec<(f64, f64)>,
-    stop_azm_elv: Vec<(f64, f64)>,
     cloud_impact_factor: f64,
 }
 
@@ -31,24 +34,23 @@ impl PVProduction {
     ///
     pub fn new(params: &ProductionParameters, lat: f64, long: f64) -> PVProduction {
         PVProduction {
+            model: params.model,
+            albedo: params.albedo,
             lat,
             long,
-            panel_power: params.panel_power,
-            panel_slope: params.panel_slope,
-            panel_east_azm: params.panel_east_azm,
+            arrays: params.arrays.clone(),
+            inverter: params.inverter.as_ref().map(Inverter::new),
             panel_temp_red: params.panel_temp_red,
             tau: params.tau,
             tau_down: params.tau_down,
             k_gain: params.k_gain,
             iam_factor: params.iam_factor,
-            start_azm_elv: params.start_azm_elv.clone(),
-            stop_azm_elv: params.stop_azm_elv.clone(),
             cloud_impact_factor: params.cloud_impact_factor,
         }
     }
 
     /// Calculate estimates for the day included in the forecast vector.
-    /// The result is an array of power per minute
+    /// The result is an array of DC power per minute, see ac_power for the inverter output
     /// 
     /// Since the algorithm is based on Utc, while the result should reflect the local time zone,
     /// we need to consider both the start time of the day (which in Utc can differ from Local)
@@ -62,13 +64,44 @@ impl PVProduction {
     pub fn estimate(&self, forecast: &ForecastValues, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Result<Vec<f64>, ProductionError> {
         let minutes = (day_end - day_start).num_minutes() as usize;
         let temp = forecast.minute_values(minutes, |f| f.temp)?;
-        let cloud_factor = forecast.minute_values(minutes, |f| f.cloud_factor)?;
-        let power_per_minute = self.day_power(day_start, day_end, &temp, &cloud_factor)?;
+
+        let power_per_minute = match self.model {
+            ProductionModel::ClearSky => {
+                let cloud_factor = forecast.minute_values(minutes, |f| f.cloud_factor)?;
+                self.day_power(day_start, day_end, &temp, &cloud_factor)?
+            },
+            ProductionModel::Irradiance => {
+                if let Some(f) = forecast.forecast.iter().find(|f| f.ghi.is_none() || f.dni.is_none() || f.dhi.is_none()) {
+                    return Err(ProductionError::MissingRadiationError(format!("no irradiance in forecast at {}", f.valid_time)));
+                }
+                let ghi = forecast.minute_values(minutes, |f| f.ghi.unwrap_or(0.0))?;
+                let dni = forecast.minute_values(minutes, |f| f.dni.unwrap_or(0.0))?;
+                let dhi = forecast.minute_values(minutes, |f| f.dhi.unwrap_or(0.0))?;
+                self.irradiance_power(day_start, day_end, &temp, &ghi, &dni, &dhi)?
+            },
+        };
         
         Ok(power_per_minute)
     }
 
-    /// Calculates one day estimated power per minute
+    /// Converts estimated DC power to AC power per minute given the configured inverter, and
+    /// returns it together with the power clipped by the inverter's AC max power.
+    /// Without an inverter configured, DC power is returned as is.
+    ///
+    /// # Arguments
+    ///
+    /// * 'dc_power' - estimated DC power per minute as given by estimate
+    pub fn ac_power(&self, dc_power: Vec<f64>) -> (Vec<f64>, Vec<f64>) {
+        match self.inverter.as_ref() {
+            Some(inverter) => inverter.ac_power(&dc_power),
+            None => {
+                let clipped = vec![0.0; dc_power.len()];
+                (dc_power, clipped)
+            },
+        }
+    }
+
+    /// Calculates one day estimated power per minute, as the sum over all PV arrays
     ///
     /// # Arguments
     ///
@@ -80,47 +113,43 @@ impl PVProduction {
         let mut power: Vec<f64> = vec![0.0;minutes];
         let sp = self.solar_positions(day_start, day_end)?;
         let sun_intensity_factor = sun_intensity_factor(&sp.zenith);
-        let up_down = self.full_sun_minute(&sp);
-        let roof_temperature_east: Vec<f64> = self.roof_temperature(&up_down, &temp, &sp.incidence_east, &sun_intensity_factor)?;
-        let roof_temperature_west: Vec<f64> = self.roof_temperature(&up_down, &temp, &sp.incidence_west, &sun_intensity_factor)?;
 
-        if sp.rise_set.len() != up_down.len() {
-            return Err(ProductionError::UnequalLengths("between rise_set and up_down vectors".to_string()));
-        }
-        
-        // Loop through the day with a one-minute incrementation
-        sp.rise_set.into_iter().zip(up_down).for_each(|((sunrise, sunset), (up, down))| {
-            for minute_of_day in sunrise..sunset {
-                // Calculate the factor on power production given sun incidence angles
-                let inc_red_e = schlick_iam(sp.incidence_east[minute_of_day], self.iam_factor);
-                let inc_red_w = schlick_iam(sp.incidence_west[minute_of_day], self.iam_factor);
+        for (array, incidence) in self.arrays.iter().zip(sp.incidence.iter()) {
+            let (up_down, direct) = shading(array, &sp);
+            let roof_temperature: Vec<f64> = self.roof_temperature(&up_down, temp, incidence, &sun_intensity_factor)?;
 
-                // Calculate power reduction due to high temperatures
-                let temp_red_e = 1.0 - (roof_temperature_east[minute_of_day].max(0.0) - 25.0) * self.panel_temp_red / 100.0;
-                let temp_red_w = 1.0 - (roof_temperature_west[minute_of_day].max(0.0) - 25.0) * self.panel_temp_red / 100.0;
+            if sp.rise_set.len() != up_down.len() {
+                return Err(ProductionError::UnequalLengths("between rise_set and up_down vectors".to_string()));
+            }
 
-                // Calculate power reduction due to the atmospheric effect given sun altitude relative to zenith
-                let ame_red = sun_intensity_factor[minute_of_day];
+            // Loop through the day with a one-minute incrementation
+            sp.rise_set.iter().for_each(|(sunrise, sunset)| {
+                for minute_of_day in *sunrise..*sunset {
+                    // Calculate the factor on power production given sun incidence angles
+                    let inc_red = schlick_iam(incidence[minute_of_day], self.iam_factor);
 
-                // Calculate total panel power where each side is reduced given the above power reduction factors
-                let pwr = self.panel_power * 12.0 * inc_red_e * temp_red_e + self.panel_power * 15.0 * inc_red_w * temp_red_w;
+                    // Calculate power reduction due to high temperatures
+                    let temp_red = 1.0 - (roof_temperature[minute_of_day].max(0.0) - 25.0) * self.panel_temp_red / 100.0;
 
-                // Calculate the shadow factors for the given minute of the day
-                let shadow_up = exp_increase(minute_of_day, sunrise, up, 10);
-                let shadow_down = exp_decrease(minute_of_day, down, sunset, 4);
+                    // Calculate power reduction due to the atmospheric effect given sun altitude relative to zenith
+                    let ame_red = sun_intensity_factor[minute_of_day];
 
-                // Calculate the cloud factor for the given minute of the day
-                let cloud_factor = cloud_factor[minute_of_day].clamp(0.0, 1.0) * self.cloud_impact_factor + (1.0 - self.cloud_impact_factor);
+                    // Calculate array power reduced given the above power reduction factors
+                    let pwr = array.panel_power * array.panels as f64 * inc_red * temp_red;
 
-                // Record the estimated power at the given point in time
-                power[minute_of_day] = pwr * ame_red * shadow_up * shadow_down * cloud_factor;
-            }
-        });
+                    // Calculate the cloud factor for the given minute of the day
+                    let cloud_factor = cloud_factor[minute_of_day].clamp(0.0, 1.0) * self.cloud_impact_factor + (1.0 - self.cloud_impact_factor);
+
+                    // Add the estimated power at the given point in time
+                    power[minute_of_day] += pwr * ame_red * direct[minute_of_day] * cloud_factor;
+                }
+            });
+        }
 
         Ok(power)
     }
 
-    /// Returns sun incidence, zenith, azimuth and elevation angles per minute in degrees for the given date.
+    /// Returns sun incidence (per PV array), zenith, azimuth and elevation angles per minute in degrees for the given date.
     ///
     /// # Arguments
     ///
@@ -135,14 +164,13 @@ impl PVProduction {
         input.pressure = 1013.0;
         input.temperature = 10.0;
         input.elevation = 61.0;
-        input.slope = self.panel_slope;
+        input.slope = 0.0;
         input.azm_rotation = 0.0;
         input.function = Function::SpaZaRts;
 
         let mut spa = SpaData::new(input);
 
-        let mut incidence_east: Vec<f64> = vec![90.0; minutes];
-        let mut incidence_west: Vec<f64> = vec![90.0; minutes];
+        let mut incidence: Vec<Vec<f64>> = vec![vec![90.0; minutes]; self.arrays.len()];
         let mut zenith: Vec<f64> = vec![90.0; minutes];
         let mut azimuth: Vec<f64> = vec![0.0; minutes];
         let mut elevation: Vec<f64> = vec![0.0; minutes];
@@ -160,24 +188,23 @@ impl PVProduction {
             if time_of_interest >= sunrise && time_of_interest < sunset {
                 spa.input.date_time(time_of_interest);
 
-                spa.input.azm_rotation = self.panel_east_azm;
-                spa.spa_calculate()?;
+                for (i, array) in self.arrays.iter().enumerate() {
+                    spa.input.slope = array.tilt;
+                    spa.input.azm_rotation = array.azimuth;
+                    spa.spa_calculate()?;
+
+                    incidence[i][toi] = spa.spa_za_inc.incidence.min(90.0);
+                }
 
-                incidence_east[toi] = spa.spa_za_inc.incidence.min(90.0);
                 zenith[toi] = spa.spa_za.zenith.clamp(0.0, 90.0);
                 azimuth[toi] = spa.spa_za.azimuth;
                 elevation[toi] = spa.spa_za.e.max(0.0);
-
-                spa.input.azm_rotation = 180.0 + self.panel_east_azm;
-                spa.spa_calculate()?;
-                incidence_west[toi] = spa.spa_za_inc.incidence.min(90.0);
             }
             time_of_interest = day_start.add(TimeDelta::minutes(toi as i64));
         }
 
         Ok(SolarPositions {
-            incidence_east,
-            incidence_west,
+            incidence,
             azimuth,
             elevation,
             zenith,
@@ -185,61 +212,6 @@ impl PVProduction {
         })
     }
 
-    /// Finds the points in time (minute) where the sun is free from nearby obstacles
-    ///
-    /// # Arguments
-    ///
-    /// * 'solar_positions' - solar positions during the day
-    fn full_sun_minute(&self, solar_positions: &SolarPositions) -> Vec<(usize, usize)> {
-        let mut up: Option<usize> = None;
-        let mut down: Option<usize> = None;
-
-        let mut up_pairs: Vec<(f64,f64,f64)> = Vec::new();
-        let obst_len = self.start_azm_elv.len();
-        for i in 0..obst_len {
-            if i < obst_len - 1 {
-                up_pairs.push((self.start_azm_elv[i].0, self.start_azm_elv[i+1].0, self.start_azm_elv[i].1));
-            } else {
-                up_pairs.push((self.start_azm_elv[i].0, 180.0, self.start_azm_elv[i].1));
-                break;
-            }
-        }
-
-        let mut down_pairs: Vec<(f64,f64,f64)> = Vec::new();
-        let obst_len = self.stop_azm_elv.len();
-        for i in 0..obst_len {
-            if i < obst_len - 1 {
-                down_pairs.push((self.stop_azm_elv[i].0, self.stop_azm_elv[i+1].0, self.stop_azm_elv[i].1));
-            } else {
-                down_pairs.push((self.stop_azm_elv[i].0, 360.0, self.stop_azm_elv[i].1));
-                break;
-            }
-        }
-
-        let mut up_down: Vec<(usize,usize)> = Vec::new();
-        solar_positions.rise_set.iter().for_each(|(sunrise, sunset)| {
-            for m in *sunrise..*sunset {
-                if solar_positions.azimuth[m] < 180.0 {
-                    for up_obst in up_pairs.iter() {
-                        if up.is_none() && solar_positions.azimuth[m] >= up_obst.0 && solar_positions.azimuth[m] < up_obst.1 && solar_positions.elevation[m] > up_obst.2 {
-                            up = Some(m);
-                        }
-                    }
-                } else {
-                    for down_obst in down_pairs.iter() {
-                        if down.is_none() && solar_positions.azimuth[m] >= down_obst.0 && solar_positions.azimuth[m] < down_obst.1 && solar_positions.elevation[m] < down_obst.2 {
-                            down = Some(m);
-                        }
-                    }
-                }
-            }
-
-            up_down.push((up.unwrap_or(0), down.unwrap_or(0)));
-        });
-
-        up_down
-    }
-
     /// Calculates roof temperature given ambient temperature and effect from direct sunlight
     ///
     /// # Arguments
@@ -273,6 +245,125 @@ impl PVProduction {
     }
 }
 
+/// Returns the points in time (minute) where the sun is free from nearby obstacles, together with
+/// a factor (0-1) per minute for how much of the direct sun reaches the array. If the array has a
+/// horizon profile, the direct sun is blocked whenever the sun is below the horizon line, otherwise
+/// the obstacle windows give exponential ramps after sunrise and before sunset.
+///
+/// # Arguments
+///
+/// * 'array' - the PV array with its horizon profile or obstacle windows
+/// * 'solar_positions' - solar positions during the day
+fn shading(array: &PVArray, solar_positions: &SolarPositions) -> (Vec<(usize, usize)>, Vec<f64>) {
+    let minutes = solar_positions.azimuth.len();
+    let mut direct: Vec<f64> = vec![0.0; minutes];
+
+    if array.horizon.is_empty() {
+        let up_down = full_sun_minute(array, solar_positions);
+        solar_positions.rise_set.iter().zip(up_down.iter()).for_each(|((sunrise, sunset), (up, down))| {
+            for (minute_of_day, d) in direct.iter_mut().enumerate().take(*sunset).skip(*sunrise) {
+                *d = exp_increase(minute_of_day, *sunrise, *up, 10) * exp_decrease(minute_of_day, *down, *sunset, 4);
+            }
+        });
+
+        return (up_down, direct);
+    }
+
+    let mut up_down: Vec<(usize, usize)> = Vec::new();
+    solar_positions.rise_set.iter().for_each(|(sunrise, sunset)| {
+        let mut up: Option<usize> = None;
+        let mut down: usize = 0;
+        for (minute_of_day, d) in direct.iter_mut().enumerate().take(*sunset).skip(*sunrise) {
+            if solar_positions.elevation[minute_of_day] > horizon_elevation(&array.horizon, solar_positions.azimuth[minute_of_day]) {
+                *d = 1.0;
+                up.get_or_insert(minute_of_day);
+                down = minute_of_day + 1;
+            }
+        }
+
+        up_down.push((up.unwrap_or(0), down));
+    });
+
+    (up_down, direct)
+}
+
+/// Returns the horizon elevation at the given azimuth, interpolated linearly between the
+/// horizon profile points and wrapping around north
+///
+/// # Arguments
+///
+/// * 'horizon' - horizon profile as azimuth and elevation pairs, ordered by azimuth (0-360)
+/// * 'azimuth' - the azimuth to get the horizon elevation for, degrees from north eastward
+fn horizon_elevation(horizon: &[(f64, f64)], azimuth: f64) -> f64 {
+    let next = horizon.iter().position(|p| p.0 >= azimuth).unwrap_or(0);
+    let prev = if next == 0 { horizon.len() - 1 } else { next - 1 };
+    let (a0, e0) = horizon[prev];
+    let (a1, e1) = horizon[next];
+
+    let span = (a1 - a0).rem_euclid(360.0);
+    if span == 0.0 {
+        return e1;
+    }
+
+    e0 + (e1 - e0) * (azimuth - a0).rem_euclid(360.0) / span
+}
+
+/// Finds the points in time (minute) where the sun is free from nearby obstacles
+///
+/// # Arguments
+///
+/// * 'array' - the PV array with its obstacle windows
+/// * 'solar_positions' - solar positions during the day
+fn full_sun_minute(array: &PVArray, solar_positions: &SolarPositions) -> Vec<(usize, usize)> {
+    let mut up: Option<usize> = None;
+    let mut down: Option<usize> = None;
+
+    let mut up_pairs: Vec<(f64,f64,f64)> = Vec::new();
+    let obst_len = array.start_azm_elv.len();
+    for i in 0..obst_len {
+        if i < obst_len - 1 {
+            up_pairs.push((array.start_azm_elv[i].0, array.start_azm_elv[i+1].0, array.start_azm_elv[i].1));
+        } else {
+            up_pairs.push((array.start_azm_elv[i].0, 180.0, array.start_azm_elv[i].1));
+            break;
+        }
+    }
+
+    let mut down_pairs: Vec<(f64,f64,f64)> = Vec::new();
+    let obst_len = array.stop_azm_elv.len();
+    for i in 0..obst_len {
+        if i < obst_len - 1 {
+            down_pairs.push((array.stop_azm_elv[i].0, array.stop_azm_elv[i+1].0, array.stop_azm_elv[i].1));
+        } else {
+            down_pairs.push((array.stop_azm_elv[i].0, 360.0, array.stop_azm_elv[i].1));
+            break;
+        }
+    }
+
+    let mut up_down: Vec<(usize,usize)> = Vec::new();
+    solar_positions.rise_set.iter().for_each(|(sunrise, sunset)| {
+        for m in *sunrise..*sunset {
+            if solar_positions.azimuth[m] < 180.0 {
+                for up_obst in up_pairs.iter() {
+                    if up.is_none() && solar_positions.azimuth[m] >= up_obst.0 && solar_positions.azimuth[m] < up_obst.1 && solar_positions.elevation[m] > up_obst.2 {
+                        up = Some(m);
+                    }
+                }
+            } else {
+                for down_obst in down_pairs.iter() {
+                    if down.is_none() && solar_positions.azimuth[m] >= down_obst.0 && solar_positions.azimuth[m] < down_obst.1 && solar_positions.elevation[m] < down_obst.2 {
+                        down = Some(m);
+                    }
+                }
+            }
+        }
+
+        up_down.push((up.unwrap_or(0), down.unwrap_or(0)));
+    });
+
+    up_down
+}
+
 /// Calculates the sunrise and sunset times for a given date.
 /// Also, it adds the rise and set times to the rise_set vector.
 ///
@@ -507,8 +598,7 @@ fn roof_thermodynamics(
 }
 
 struct SolarPositions {
-    incidence_east: Vec<f64>,
-    incidence_west: Vec<f64>,
+    incidence: Vec<Vec<f64>>,
     azimuth: Vec<f64>,
     elevation: Vec<f64>,
     zenith: Vec<f64>,
@@ -527,4 +617,31 @@ pub enum ProductionError {
     ThermodynamicsError(String),
     #[error("UnequalLengths: {0}")]
     UnequalLengths(String),
-}
\ No newline at end of file
+    #[error("MissingRadiationError: {0}")]
+    MissingRadiationError(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_horizon_elevation_between_points() {
+        let horizon = [(10.0, 2.0), (180.0, 20.0), (350.0, 6.0)];
+
+        assert!((horizon_elevation(&horizon, 95.0) - 11.0).abs() < 1e-9);
+        assert_eq!(horizon_elevation(&horizon, 180.0), 20.0);
+        assert!((horizon_elevation(&horizon, 265.0) - 13.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn wraps_horizon_elevation_around_north() {
+        let horizon = [(10.0, 2.0), (180.0, 20.0), (350.0, 6.0)];
+
+        assert!((horizon_elevation(&horizon, 355.0) - 5.0).abs() < 1e-9);
+        assert!((horizon_elevation(&horizon, 0.0) - 4.0).abs() < 1e-9);
+        assert!((horizon_elevation(&horizon, 5.0) - 3.0).abs() < 1e-9);
+        assert_eq!(horizon_elevation(&horizon, 10.0), 2.0);
+        assert_eq!(horizon_elevation(&[(90.0, 7.0)], 300.0), 7.0);
+    }
+}
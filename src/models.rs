@@ -2,25 +2,39 @@ use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::ops::Add;
 use chrono::{DateTime, TimeDelta, Utc};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use anyhow::Result;
 use thiserror::Error;
 use crate::spline::{MonotonicCubicSpline, SplineError};
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct BaseData {
     pub date_time: DateTime<Utc>,
     pub base_cost: f64,
     pub schedule_cost: f64,
+    pub residual_value: f64,
     pub soc_kwh: f64,
     pub forecast: Vec<ForecastValue>,
     pub production: Vec<TimeValue>,
+    #[serde(default)]
+    pub clipped: Vec<TimeValue>,
     pub consumption: Vec<TimeValue>,
     pub tariffs: Vec<TariffValue>,
-    pub tariff_fees: TariffFees,
+    // Fees of the default price formula, omitted when a tariff formula is configured since the
+    // formula and the per quarter variable fee in tariffs then give the prices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tariff_fees: Option<TariffFees>,
 }
 
+/// Machine-readable hint that the scheduler should be run again, e.g. since the schedule was
+/// made provisionally on a partial horizon
 #[derive(Serialize, Debug)]
+pub struct RetryHint {
+    pub retry_after: DateTime<Utc>,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct TariffFees {
     // Power grid fees (öre/kWh, exl. VAT)
     pub variable_fee: f64,
@@ -40,19 +54,23 @@ pub struct TariffFees {
 
 pub struct PreformattedData {
     pub tariffs: Vec<f64>,
+    pub sell_tariffs: Vec<f64>,
     pub cons: Vec<f64>,
     pub net_prod: Vec<f64>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct TariffValue {
     pub valid_time: DateTime<Utc>,
     pub price: f64,
     pub buy: f64,
     pub sell: f64,
+    pub variable_fee: f64,
+    #[serde(default)]
+    pub estimated: bool,
 }
 
-#[derive(Clone, Serialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct TimeValue {
     pub valid_time: DateTime<Utc>,
     pub data: f64
@@ -68,7 +86,7 @@ pub struct MinuteValues<'a> {
     pub data: &'a [f64],
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ForecastValue {
     pub valid_time: DateTime<Utc>,
     pub temp: f64,
@@ -76,6 +94,9 @@ pub struct ForecastValue {
     pub mcc_mean: f64,
     pub hcc_mean: f64,
     pub cloud_factor: f64,
+    pub ghi: Option<f64>,
+    pub dni: Option<f64>,
+    pub dhi: Option<f64>,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Add;
+use chrono::{DateTime, Datelike, DurationRound, Local, TimeDelta, Timelike, Utc};
+use foxess::{Fox, FoxVariables};
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use thiserror::Error;
+use crate::config::PowerFee;
+use crate::models::TimeValue;
+
+/// Record of the highest hourly grid import peaks (kW) measured so far in the current month
+/// within the power fee peak hours
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PowerPeaks {
+    pub month: String,
+    pub measured_to: DateTime<Utc>,
+    pub peaks: Vec<TimeValue>,
+}
+
+impl PowerPeaks {
+    /// Loads the power peaks record for the month of the given date time. A new, empty, record
+    /// is returned if there is no record saved or if the saved record is from another month.
+    ///
+    /// # Arguments
+    ///
+    /// * 'path' - path to the power peaks file
+    /// * 'date_time' - a date time within the month of interest
+    pub fn load(path: &str, date_time: DateTime<Utc>) -> Result<PowerPeaks, PowerPeaksError> {
+        let month = date_time.with_timezone(&Local).format("%Y-%m").to_string();
+
+        if let Ok(json) = fs::read_to_string(path) {
+            let record: PowerPeaks = serde_json::from_str(&json)?;
+            if record.month == month {
+                return Ok(record);
+            }
+        }
+
+        Ok(PowerPeaks {
+            month,
+            measured_to: month_start(date_time),
+            peaks: Vec::new(),
+        })
+    }
+
+    /// Saves the power peaks record
+    ///
+    /// # Arguments
+    ///
+    /// * 'path' - path to the power peaks file
+    pub fn save(&self, path: &str) -> Result<(), PowerPeaksError> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)?;
+
+        Ok(())
+    }
+
+    /// Updates the record with hourly average grid import from Fox ESS history, from where the
+    /// record was last measured up to the last whole hour before the given date time.
+    ///
+    /// # Arguments
+    ///
+    /// * 'fox' - Fox ESS client
+    /// * 'power_fee' - power fee configuration
+    /// * 'until' - date time to measure up to (non-inclusive, truncated to whole hours)
+    pub fn update(&mut self, fox: &Fox, power_fee: &PowerFee, until: DateTime<Utc>) -> Result<(), PowerPeaksError> {
+        let until = until.duration_trunc(TimeDelta::hours(1))
+            .map_err(|e| PowerPeaksError::DateError(e.to_string()))?;
+
+        while self.measured_to < until {
+            let to = self.measured_to.add(TimeDelta::hours(24)).min(until);
+            let history = fox.get_variables_history(self.measured_to, to, vec![FoxVariables::GridConsumptionPower])?;
+
+            let mut hours: HashMap<DateTime<Utc>, (f64, f64)> = HashMap::new();
+            for d in history.get(FoxVariables::GridConsumptionPower).unwrap_or_default() {
+                if let Ok(hour) = d.date_time.duration_trunc(TimeDelta::hours(1)) {
+                    let _ = hours
+                        .entry(hour)
+                        .and_modify(|v| { v.0 += d.data; v.1 += 1.0; })
+                        .or_insert((d.data, 1.0));
+                }
+            }
+
+            hours.into_iter()
+                .filter(|(hour, _)| *hour >= self.measured_to && *hour < to && is_peak_hour(power_fee, *hour))
+                .for_each(|(hour, (sum, count))| self.peaks.push(TimeValue { valid_time: hour, data: sum / count }));
+
+            self.peaks.sort_by(|a, b| b.data.total_cmp(&a.data));
+            self.peaks.truncate(power_fee.peaks_averaged);
+            self.measured_to = to;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the level (kW) a new hourly peak has to exceed to raise the monthly fee, i.e. the
+    /// lowest of the peaks that are averaged. If fewer peaks than that are recorded, any import
+    /// within peak hours raises the fee.
+    ///
+    /// # Arguments
+    ///
+    /// * 'peaks_averaged' - number of highest hourly peaks averaged per month
+    pub fn peak_level(&self, peaks_averaged: usize) -> f64 {
+        if self.peaks.len() < peaks_averaged {
+            0.0
+        } else {
+            self.peaks.get(peaks_averaged.max(1) - 1).map(|p| p.data).unwrap_or(0.0)
+        }
+    }
+}
+
+/// Returns whether the hour starting at the given date time is within the power fee peak hours
+///
+/// # Arguments
+///
+/// * 'power_fee' - power fee configuration
+/// * 'date_time' - date time to check
+pub fn is_peak_hour(power_fee: &PowerFee, date_time: DateTime<Utc>) -> bool {
+    let local = date_time.with_timezone(&Local);
+
+    if !power_fee.months.is_empty() && !power_fee.months.contains(&local.month()) {
+        return false;
+    }
+    if power_fee.weekdays_only && local.weekday().num_days_from_monday() > 4 {
+        return false;
+    }
+
+    power_fee.peak_hours.iter().any(|(start, end)| local.hour() >= *start && local.hour() < *end)
+}
+
+/// Returns whether the given date time is in a later month (in local time) than the reference
+///
+/// # Arguments
+///
+/// * 'date_time' - date time to check
+/// * 'reference' - a date time within the reference month
+pub fn is_later_month(date_time: DateTime<Utc>, reference: DateTime<Utc>) -> bool {
+    month_start(date_time) > month_start(reference)
+}
+
+/// Returns the start of the month (in local time) for the given date time
+///
+/// # Arguments
+///
+/// * 'date_time' - a date time within the month
+fn month_start(date_time: DateTime<Utc>) -> DateTime<Utc> {
+    let local = date_time.with_timezone(&Local);
+    local.date_naive()
+        .with_day(1).unwrap()
+        .and_hms_opt(0, 0, 0).unwrap()
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|d| d.with_timezone(&Utc))
+        .unwrap_or(date_time)
+}
+
+/// Error depicting errors that occur while managing power peaks
+///
+#[derive(Debug, Error)]
+pub enum PowerPeaksError {
+    #[error("DateError: {0}")]
+    DateError(String),
+    #[error("DocumentError: {0}")]
+    DocumentError(#[from] serde_json::Error),
+    #[error("FileError: {0}")]
+    FileError(#[from] std::io::Error),
+    #[error("FoxError: {0}")]
+    FoxError(#[from] foxess::FoxError),
+}
@@ -0,0 +1,248 @@
+use crate::scheduler::{BlockCollection, BlockInternal, BlockType, PeriodMetrics, Schedule};
+
+/// A small virtual cost (SEK) added whenever the plan switches block type. It is not part of
+/// the reported cost, it only keeps the optimizer from fragmenting the schedule when
+/// alternatives are (almost) equally priced.
+const BLOCK_SWITCH_COST: f64 = 0.005;
+
+/// One reachable state in the dynamic programming grid, i.e. the cheapest way found so far
+/// to arrive at a given (discretised) charge level at the start of a given quarter
+#[derive(Clone)]
+struct State {
+    total_cost: f64,
+    charge: f64,
+    hold_level: f64,
+    continues: bool,
+    step: Option<Step>,
+}
+
+/// The transition that led into a state, kept for backtracking the optimal plan
+#[derive(Clone)]
+struct Step {
+    prev_level: usize,
+    block_type: BlockType,
+    charge_in: f64,
+    cost: f64,
+}
+
+impl Schedule<'_> {
+    /// Seeks the best schedule using dynamic programming over (quarter index x discretised charge level).
+    ///
+    /// In contrast to the combinatorial search, this optimizer is not limited in the number of
+    /// charge/use cycles. Each quarter is evaluated as either Use, Hold or Charge using the same
+    /// semantics as `update_for_pv`. A quarter continuing a Hold holds at the level the Hold started
+    /// at, since that is the charge in the executor holds at for the merged block. If `max_export_kw`
+    /// is configured, quarters can also be evaluated as Sell (forced discharge to the grid). Charge levels are
+    /// discretised in steps of one SoC percent while the exact charge is carried within each state,
+    /// so small additions from PV are not lost to rounding.
+    ///
+    /// # Arguments
+    ///
+    /// * 'charge_in' - the charge in the battery when the first block after pre-blocks starts
+    /// * 'pre_blocks' - the number of blocks to skip before starting the search
+    pub(super) fn dynamic_search(&self, charge_in: f64, pre_blocks: usize) -> BlockCollection {
+        let levels = (self.bat_kwh / self.soc_kwh).round() as usize;
+        let quarters = self.schedule_length - pre_blocks;
+
+        let mut grid: Vec<Vec<Option<State>>> = vec![vec![None; levels + 1]; quarters + 1];
+        grid[0][self.charge_level(charge_in, levels)] = Some(State { total_cost: 0.0, charge: charge_in, hold_level: charge_in, continues: false, step: None });
+
+        for q in 0..quarters {
+            let idx = q + pre_blocks;
+            let (current, next) = grid.split_at_mut(q + 1);
+            let next = &mut next[0];
+
+            for (level, state) in current[q].iter().enumerate() {
+                let Some(state) = state else { continue };
+                let prev_type = state.step.as_ref().map(|s| &s.block_type);
+
+                for block_type in [BlockType::Use, BlockType::Hold] {
+                    let continues = prev_type == Some(&block_type);
+                    let pm = if block_type == BlockType::Hold && continues {
+                        self.hold_quarter(idx, state.charge, state.hold_level)
+                    } else {
+                        self.update_for_pv(block_type.clone(), idx, idx + 1, state.charge)
+                    };
+                    let candidate = State {
+                        total_cost: state.total_cost + pm.cost + switch_cost(continues),
+                        charge: pm.charge_out,
+                        hold_level: pm.hold_level,
+                        continues,
+                        step: Some(Step { prev_level: level, block_type, charge_in: state.charge, cost: pm.cost }),
+                    };
+                    relax(next, self.charge_level(pm.charge_out, levels), candidate);
+                }
+
+                let cons_cost = self.update_for_pv(BlockType::Charge, idx, idx + 1, 0.0).cost;
+                for target in (level + 1)..=levels {
+                    let charge_out = target as f64 * self.soc_kwh;
+                    let need = (charge_out - state.charge) / self.charge_efficiency;
+                    if need > self.charge_limit(idx) + 1e-9 {
+                        break;
+                    }
+                    let cost = self.charge_quarter_cost(idx, need) + cons_cost;
+                    let continues = prev_type == Some(&BlockType::Charge);
+                    let candidate = State {
+                        total_cost: state.total_cost + cost + switch_cost(continues),
+                        charge: charge_out,
+                        hold_level: charge_out,
+                        continues,
+                        step: Some(Step { prev_level: level, block_type: BlockType::Charge, charge_in: state.charge, cost }),
+                    };
+                    relax(next, target, candidate);
+                }
+
+                if self.sell_kwh_instance > 0.0 {
+                    for target in (0..level).rev() {
+                        let charge_out = target as f64 * self.soc_kwh;
+                        let discharge = state.charge - charge_out;
+                        if discharge * self.discharge_efficiency > self.sell_limit(idx) + 1e-9 {
+                            break;
+                        }
+                        let cost = self.sell_cost(idx, discharge);
+                        let continues = prev_type == Some(&BlockType::Sell);
+                        let candidate = State {
+                            total_cost: state.total_cost + cost + switch_cost(continues),
+                            charge: charge_out,
+                            hold_level: charge_out,
+                            continues,
+                            step: Some(Step { prev_level: level, block_type: BlockType::Sell, charge_in: state.charge, cost }),
+                        };
+                        relax(next, target, candidate);
+                    }
+                }
+            }
+        }
+
+        self.collect_dynamic_blocks(&grid, pre_blocks)
+    }
+
+    /// Returns the discretised charge level for a given charge
+    ///
+    /// # Arguments
+    ///
+    /// * 'charge' - charge in kWh
+    /// * 'levels' - the highest available level
+    fn charge_level(&self, charge: f64, levels: usize) -> usize {
+        ((charge / self.soc_kwh).round().max(0.0) as usize).min(levels)
+    }
+
+    /// Evaluates one quarter continuing a Hold block, i.e. holding at the level the block started at
+    /// rather than at the charge the quarter starts with
+    ///
+    /// # Arguments
+    ///
+    /// * 'idx' - index of the quarter
+    /// * 'charge_in' - the charge in the battery when the quarter starts
+    /// * 'hold_level' - the charge in of the Hold block the quarter continues
+    fn hold_quarter(&self, idx: usize, charge_in: f64, hold_level: f64) -> PeriodMetrics {
+        let mut pm = PeriodMetrics {
+            block_type: BlockType::Hold,
+            start: idx,
+            size: 1,
+            charge_in,
+            charge_out: charge_in,
+            hold_level,
+            cost: 0.0,
+        };
+        self.add_net_prod(idx, self.net_prod[idx], &mut pm);
+
+        pm
+    }
+
+    /// Backtracks the cheapest final state through the grid and merges consecutive quarters
+    /// of the same block type into blocks
+    ///
+    /// # Arguments
+    ///
+    /// * 'grid' - the filled dynamic programming grid
+    /// * 'pre_blocks' - the number of blocks that has been skipped
+    fn collect_dynamic_blocks(&self, grid: &[Vec<Option<State>>], pre_blocks: usize) -> BlockCollection {
+        let quarters = grid.len() - 1;
+        let (mut level, last) = grid[quarters].iter()
+            .enumerate()
+            .filter_map(|(l, s)| s.as_ref().map(|s| (l, s)))
+            .min_by(|a, b| {
+                let a_cost = a.1.total_cost - self.residual_value(a.1.charge);
+                let b_cost = b.1.total_cost - self.residual_value(b.1.charge);
+                a_cost.total_cmp(&b_cost)
+            })
+            .expect("there should always be at least one reachable final state");
+
+        let next_charge_in = last.charge;
+
+        let mut blocks: Vec<BlockInternal> = Vec::new();
+        for q in (0..quarters).rev() {
+            let state = grid[q + 1][level].as_ref().expect("backtracked state should exist");
+            let step = state.step.as_ref().expect("backtracked state should have a step");
+
+            match blocks.last_mut() {
+                Some(b) if b.block_type == step.block_type => {
+                    b.start_hour -= 1;
+                    b.size += 1;
+                    b.cost += step.cost;
+                    b.charge_in = step.charge_in;
+                },
+                _ => blocks.push(BlockInternal {
+                    block_type: step.block_type.clone(),
+                    start_hour: q + pre_blocks,
+                    size: 1,
+                    cost: step.cost,
+                    charge_in: step.charge_in,
+                    charge_out: state.charge,
+                    charge_kw_limit: None,
+                }),
+            }
+            level = step.prev_level;
+        }
+        blocks.reverse();
+        blocks.iter_mut()
+            .filter(|b| b.block_type == BlockType::Charge)
+            .for_each(|b| b.charge_kw_limit = self.charge_kw_limit(b.start_hour, b.size));
+
+        let total_cost = (blocks.iter().map(|b| b.cost).sum::<f64>() * 100.0).round() / 100.0;
+
+        BlockCollection {
+            blocks,
+            next_start: self.schedule_length,
+            next_charge_in,
+            total_cost,
+            residual_value: self.residual_value(next_charge_in),
+        }
+    }
+}
+
+/// Returns the virtual switch cost for a transition
+///
+/// # Arguments
+///
+/// * 'continues' - whether the transition continues the block type of its predecessor
+fn switch_cost(continues: bool) -> f64 {
+    if continues { 0.0 } else { BLOCK_SWITCH_COST }
+}
+
+/// Records a candidate state if it is cheaper than what is already recorded for the level.
+/// On equal cost, a candidate that continues the block type of its predecessor is preferred
+/// since that gives fewer blocks in the resulting schedule.
+///
+/// # Arguments
+///
+/// * 'states' - the states for the next quarter
+/// * 'level' - the level the candidate arrives at
+/// * 'candidate' - the candidate state
+fn relax(states: &mut [Option<State>], level: usize, candidate: State) {
+    let better = match &states[level] {
+        None => true,
+        Some(current) => {
+            if candidate.total_cost < current.total_cost - 1e-9 {
+                true
+            } else {
+                candidate.total_cost <= current.total_cost + 1e-9 && candidate.continues && !current.continues
+            }
+        }
+    };
+
+    if better {
+        states[level] = Some(candidate);
+    }
+}
@@ -1,13 +1,16 @@
+mod dynamic;
+
 use std::ops::Add;
 use std::fmt;
 use std::fmt::Formatter;
-use chrono::{DateTime, DurationRound, TimeDelta, Timelike, Utc};
+use chrono::{DateTime, TimeDelta, Timelike, Utc};
 use serde::{Deserialize, Serialize};
 use crate::models::{TimeValue, TariffValue, PreformattedData};
 use rayon::prelude::*;
 use thiserror::Error;
 use anyhow::Result;
-use crate::config::Config;
+use crate::config::{Config, Optimizer, PowerFee};
+use crate::power_peaks::{is_later_month, is_peak_hour};
 
 
 /// Available block types
@@ -16,6 +19,7 @@ pub enum BlockType {
     Charge,
     Hold,
     Use,
+    Sell,
 }
 
 /// Implementation of the Display Trait for pretty print
@@ -25,6 +29,7 @@ impl fmt::Display for BlockType {
             BlockType::Charge => write!(f, "Charge"),
             BlockType::Hold   => write!(f, "Hold  "),
             BlockType::Use    => write!(f, "Use   "),
+            BlockType::Sell   => write!(f, "Sell  "),
         }
     }
 }
@@ -68,6 +73,9 @@ pub struct Block {
     pub soc_in: usize,
     pub soc_out: usize,
     soc_kwh: f64,
+    pub charge_kw_limit: Option<f64>,
+    #[serde(default)]
+    pub estimated: bool,
     pub status: Status,
 }
 
@@ -75,7 +83,7 @@ pub struct Block {
 impl fmt::Display for Block {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         // Build base output
-        let output = format!("{} -> {:>02}:{:>02} - {:>02}:{:>02}: SocIn {:>3}, SocOut {:>3}, chargeIn {:>5.2}, chargeOut {:>5.2}, cost {:>5.2} ",
+        let mut output = format!("{} -> {:>02}:{:>02} - {:>02}:{:>02}: SocIn {:>3}, SocOut {:>3}, chargeIn {:>5.2}, chargeOut {:>5.2}, cost {:>5.2} ",
                              self.block_type,
                              self.start_hour, self.start_minute,
                              self.end_hour, self.end_minute,
@@ -83,6 +91,16 @@ impl fmt::Display for Block {
                              self.charge_in, self.charge_out,
                              self.cost);
 
+        // Add charge limit if import is limited by the main fuse
+        if let Some(limit) = self.charge_kw_limit {
+            output = format!("{}chargeLimit {:>5.2} kW ", output, limit);
+        }
+
+        // Flag blocks that are planned on estimated prices
+        if self.estimated {
+            output = format!("{}estimated ", output);
+        }
+
         write!(f, "{}", output)
     }
 }
@@ -95,6 +113,7 @@ struct BlockInternal {
     cost: f64,
     charge_in: f64,
     charge_out: f64,
+    charge_kw_limit: Option<f64>,
 }
 
 #[derive(Default, Clone)]
@@ -103,6 +122,15 @@ struct BlockCollection {
     next_start: usize,
     next_charge_in: f64,
     total_cost: f64,
+    residual_value: f64,
+}
+
+impl BlockCollection {
+    /// Returns the cost used when comparing schedules, i.e. the total cost less the value
+    /// of any energy left in the battery at the end of the schedule
+    fn net_cost(&self) -> f64 {
+        self.total_cost - self.residual_value
+    }
 }
 
 struct PeriodMetrics {
@@ -124,16 +152,21 @@ pub struct SchedulerResult {
     #[serde(skip)]
     pub total_cost: f64,
     #[serde(skip)]
+    pub residual_value: f64,
+    #[serde(skip)]
     pub start_time: DateTime<Utc>,
     #[serde(skip)]
     pub end_time: DateTime<Utc>,
     pub blocks: Vec<Block>,
     pub schedule_id: i64,
+    pub provisional: bool,
+    pub estimated_from: Option<DateTime<Utc>>,
 }
 
 /// Struct representing the block schedule from the current hour and forward
 pub struct Schedule<'a> {
     tariffs: &'a[f64],
+    sell_tariffs: &'a[f64],
     base_cost: f64,
     net_prod: &'a[f64],
     cons: &'a[f64],
@@ -141,10 +174,18 @@ pub struct Schedule<'a> {
     bat_kwh: f64,
     pub(crate) soc_kwh: f64,
     charge_kwh_instance: f64,
+    max_import_kwh_instance: Option<f64>,
+    sell_kwh_instance: f64,
+    max_export_kwh_instance: Option<f64>,
     charge_efficiency: f64,
     discharge_efficiency: f64,
     min_saving: f64,
+    terminal_value: f64,
+    power_fee: Option<PowerFee>,
+    peak_kw: f64,
+    peak_levels: Vec<Option<f64>>,
     mode_scheduler: bool,
+    optimizer: Optimizer,
 }
 
 impl<'a> Schedule<'a> {
@@ -154,10 +195,13 @@ impl<'a> Schedule<'a> {
     ///
     /// * 'config' - configuration struct
     /// * 'soh' - battery's current state of health
-    pub fn new(config: &Config, soh: u8) -> Schedule<'_> {
+    /// * 'terminal_value' - value (SEK/kWh) of energy left in the battery at the end of the schedule
+    /// * 'peak_kw' - the level a new hourly import peak has to exceed to raise this month's power fee
+    pub fn new(config: &Config, soh: u8, terminal_value: f64, peak_kw: f64) -> Schedule<'_> {
         let bat_capacity = config.charge.bat_capacity_kwh * (soh as f64 / 100.0);
         Schedule {
             tariffs: &[0.0],
+            sell_tariffs: &[0.0],
             base_cost: 0.0,
             net_prod: &[0.0],
             cons: &[0.0],
@@ -165,10 +209,23 @@ impl<'a> Schedule<'a> {
             bat_kwh: bat_capacity * 0.9,
             soc_kwh: bat_capacity / 100.0,
             charge_kwh_instance: config.charge.charge_kwh_hour,
+            max_import_kwh_instance: config.charge.max_import_kw.map(|kw| kw / 4.0),
+            sell_kwh_instance: match config.scheduler.optimizer {
+                Optimizer::Search => 0.0,
+                Optimizer::DynamicProgramming => config.charge.max_export_kw / 4.0,
+            },
+            max_export_kwh_instance: config.production.inverter.as_ref()
+                .and_then(|i| i.export_limit)
+                .map(|w| w / 1000.0 / 4.0),
             charge_efficiency: config.charge.charge_efficiency,
             discharge_efficiency: config.charge.discharge_efficiency,
             min_saving: config.scheduler.min_saving,
+            terminal_value,
+            power_fee: config.tariff_fees.power_fee.clone(),
+            peak_kw,
+            peak_levels: Vec::new(),
             mode_scheduler: config.scheduler.mode_scheduler,
+            optimizer: config.scheduler.optimizer,
         }
     }
 
@@ -194,6 +251,10 @@ impl<'a> Schedule<'a> {
             .filter(|t| t.valid_time >= start_time && t.valid_time < end_time)
             .map(|t| t.buy)
             .collect::<Vec<f64>>();
+        let sell_tariffs: Vec<f64> = nordpool_tariffs.iter()
+            .filter(|t| t.valid_time >= start_time && t.valid_time < end_time)
+            .map(|t| t.sell)
+            .collect::<Vec<f64>>();
         let capacity = tariffs.len();
 
         let mut prod: Vec<f64> = Vec::with_capacity(capacity);
@@ -216,6 +277,7 @@ impl<'a> Schedule<'a> {
         } else {
             Ok(PreformattedData {
                 tariffs,
+                sell_tariffs,
                 cons,
                 net_prod,
             })            
@@ -228,22 +290,29 @@ impl<'a> Schedule<'a> {
     ///
     /// # Arguments
     ///
-    /// * 'tariffs' - tariffs as given from NordPool
-    /// * 'cons' - consumption estimates per quarter
+    /// * 'pd' - preformatted tariffs, sell prices, consumption and net production (production - consumption) per quarter
     /// * 'soc_in' - any residual charge to bear in to the new schedule (stated as soc 0-100)
-    /// * 'net_prod' - net production estimates per quarter (production - consumption)
     /// * 'run_start' - the date time when the scheduler run starts (to calculate SoC for schedule start)
     /// * 'start_time' - the date time when the schedule shall start
-    pub fn update_scheduling(&mut self, tariffs: &'a[f64], cons: &'a[f64], net_prod: &'a[f64], soc_in: u8, run_start: DateTime<Utc>, start_time: DateTime<Utc>) -> SchedulerResult {
+    pub fn update_scheduling(&mut self, pd: &'a PreformattedData, soc_in: u8, run_start: DateTime<Utc>, start_time: DateTime<Utc>) -> SchedulerResult {
         let charge_in = (soc_in.max(10) - 10) as f64 * self.soc_kwh;
 
-        self.tariffs = tariffs;
-        self.cons = cons;
-        self.net_prod = net_prod;
-        self.schedule_length = tariffs.len();
+        self.tariffs = &pd.tariffs;
+        self.sell_tariffs = &pd.sell_tariffs;
+        self.cons = &pd.cons;
+        self.net_prod = &pd.net_prod;
+        self.schedule_length = pd.tariffs.len();
+        self.peak_levels = (0..self.schedule_length)
+            .map(|i| {
+                let quarter = run_start.add(TimeDelta::minutes(i as i64 * 15));
+                self.power_fee.as_ref()
+                    .filter(|pf| is_peak_hour(pf, quarter))
+                    .map(|_| if is_later_month(quarter, run_start) { 0.0 } else { self.peak_kw })
+            })
+            .collect();
 
         let pre_blocks = (start_time - run_start).num_minutes() / 15;
-        let block_collection = self.parallel_search(charge_in, pre_blocks as usize);
+        let block_collection = self.search(charge_in, pre_blocks as usize);
         let blocks = create_result_blocks(block_collection.blocks, pre_blocks as usize, self.soc_kwh, start_time);
 
         SchedulerResult {
@@ -251,44 +320,61 @@ impl<'a> Schedule<'a> {
             soc_kwh: self.soc_kwh,
             base_cost: self.base_cost,
             total_cost: block_collection.total_cost,
+            residual_value: block_collection.residual_value,
             start_time,
             end_time: blocks.last().expect("should exist at least the base block").end_time.add(TimeDelta::minutes(15)),
             blocks,
             schedule_id: Utc::now().timestamp(),
+            provisional: false,
+            estimated_from: None,
         }
     }
 
-    /// Function to break up the scheduling process over parallel threads
-    /// 
+    /// Runs the configured optimizer and returns its result if it beats the base schedule
+    /// (full day Use) with at least the configured minimum saving
+    ///
     /// # Arguments
-    /// 
+    ///
     /// * 'charge_in' - the charge in the battery when the schedule starts
     /// * 'pre_blocks' - the number of blocks to skip
-    fn parallel_search(&mut self, charge_in: f64, pre_blocks: usize) -> BlockCollection {
+    fn search(&mut self, charge_in: f64, pre_blocks: usize) -> BlockCollection {
         let pm = self.update_for_pv(BlockType::Use, 0, pre_blocks, charge_in);
+        let base_record = self.create_base_block_collection(pm.charge_out, pre_blocks);
+
+        let best_record = match self.optimizer {
+            Optimizer::Search => self.parallel_search(pm.charge_out, pre_blocks, base_record.clone()),
+            Optimizer::DynamicProgramming => self.dynamic_search(pm.charge_out, pre_blocks),
+        };
 
-        let mut best_record: BlockCollection = self.create_base_block_collection(pm.charge_out, pre_blocks);
-        let base_record = best_record.clone();
+        if best_record.net_cost() < base_record.net_cost() - self.min_saving {
+            best_record
+        } else {
+            base_record
+        }
+    }
+
+    /// Function to break up the scheduling process over parallel threads
+    /// 
+    /// # Arguments
+    /// 
+    /// * 'charge_in' - the charge in the battery when the first block after pre-blocks starts
+    /// * 'pre_blocks' - the number of blocks to skip
+    /// * 'base_record' - the base block collection to compare with
+    fn parallel_search(&self, charge_in: f64, pre_blocks: usize, base_record: BlockCollection) -> BlockCollection {
+        let mut best_record = base_record;
 
         let bcs = (pre_blocks..self.schedule_length).into_par_iter()
-            .map(|seek_first_charge| self.seek_best(pm.charge_out, pre_blocks, seek_first_charge, best_record.clone()))
+            .map(|seek_first_charge| self.seek_best(charge_in, pre_blocks, seek_first_charge, best_record.clone()))
             .collect::<Vec<BlockCollection>>();
 
         for bc in bcs {
-            if bc.total_cost < best_record.total_cost {
+            if bc.net_cost() < best_record.net_cost()
+                || (bc.net_cost() == best_record.net_cost() && bc.blocks.len() < best_record.blocks.len()) {
                 best_record = bc;
-            } else if bc.total_cost == best_record.total_cost {
-                if bc.blocks.len() < best_record.blocks.len() {
-                    best_record = bc;
-                }
             }
         }
 
-        if best_record.total_cost < base_record.total_cost - self.min_saving {
-            best_record
-        } else {
-            base_record
-        }
+        best_record
     }
 
     /// Seeks the best schedule given input parameters.
@@ -352,6 +438,7 @@ impl<'a> Schedule<'a> {
             next_start: self.schedule_length,
             next_charge_in: block.charge_out,
             total_cost: self.base_cost,
+            residual_value: self.residual_value(block.charge_out),
             blocks: vec![block],
         }
     }
@@ -378,14 +465,14 @@ impl<'a> Schedule<'a> {
 
         let need = (soc_level as f64 * self.soc_kwh - pm_hold.charge_out) / self.charge_efficiency;
         if need > 0.0 {
-            let (c_cost, end) = self.charge_cost_charge_end(start, need);
+            let (c_cost, end, charged) = self.charge_cost_charge_end(start, need);
             let pm_charge = self.update_for_pv(BlockType::Charge, start, end, 0.0);
 
             next_start += end - start;
             total_cost += c_cost + pm_charge.cost;
 
             if pm_charge.size > 0 {
-                next_charge_in = soc_level as f64 * self.soc_kwh;
+                next_charge_in = pm_hold.charge_out + charged * self.charge_efficiency;
                 blocks.push(self.get_charge_block(start, pm_charge.size, pm_hold.charge_out, next_charge_in, c_cost + pm_charge.cost));
             }
         }
@@ -394,32 +481,121 @@ impl<'a> Schedule<'a> {
             next_start,
             next_charge_in,
             total_cost,
+            residual_value: 0.0,
             blocks,
         }
     }
 
     /// Calculates the cost for a given charge from grid at a given start time
-    /// It also returns the expected end for the charging period
+    /// It also returns the expected end for the charging period and the charge that fits before
+    /// the end of the schedule. Quarters where the import limit leaves less room for charging
+    /// get a reduced charge, which moves the rest of the charge to later quarters.
     ///
     /// # Arguments
     ///
     /// * 'start' - start instance for charging from grid
     /// * 'charge' - charge in kWh
-    fn charge_cost_charge_end(&self, start: usize, charge: f64) -> (f64, usize) {
-        let mut instance_charge: Vec<f64> = Vec::new();
-        let rem = charge % self.charge_kwh_instance;
+    fn charge_cost_charge_end(&self, start: usize, charge: f64) -> (f64, usize, f64) {
+        let mut remaining = charge;
+        let mut c_price = 0.0;
+        let mut end = start;
+
+        while end < self.schedule_length && (remaining * 10.0).round() as usize != 0 {
+            let instance_charge = remaining.min(self.charge_limit(end));
+            c_price += self.charge_quarter_cost(end, instance_charge);
+            remaining -= instance_charge;
+            end += 1;
+        }
+
+        let charged = if (remaining * 10.0).round() as usize == 0 { charge } else { charge - remaining };
+
+        (c_price, end, charged)
+    }
 
-        (0..(charge / self.charge_kwh_instance) as usize).for_each(|_| instance_charge.push(self.charge_kwh_instance));
-        if (rem * 10.0).round() as usize != 0 {
-            instance_charge.push(rem);
+    /// Returns the max charge from grid (kWh) for one quarter, i.e. the typical charge per quarter
+    /// reduced to what the import limit leaves room for on top of household consumption
+    ///
+    /// # Arguments
+    ///
+    /// * 'idx' - index of the time instance
+    fn charge_limit(&self, idx: usize) -> f64 {
+        match self.max_import_kwh_instance {
+            Some(max_import) => (max_import - self.cons[idx]).clamp(0.0, self.charge_kwh_instance),
+            None => self.charge_kwh_instance,
         }
-        let end = (start + instance_charge.len()).min(self.schedule_length);
-        let c_price = self.tariffs[start..end].iter()
-            .enumerate()
-            .map(|(i, t)| instance_charge[i] * t)
-            .sum::<f64>();
+    }
+
+    /// Returns the max discharge (kWh, grid side) that can be exported during one quarter of
+    /// forced discharge, i.e. the sell limit, reduced to any grid export limit, less any PV surplus
+    /// exported in the same quarter
+    ///
+    /// # Arguments
+    ///
+    /// * 'idx' - index of the time instance
+    fn sell_limit(&self, idx: usize) -> f64 {
+        (self.sell_kwh_instance.min(self.export_limit()) - self.net_prod[idx].max(0.0)).max(0.0)
+    }
+
+    /// Returns the max energy (kWh) that can be exported to the grid during one quarter
+    fn export_limit(&self) -> f64 {
+        self.max_export_kwh_instance.unwrap_or(f64::INFINITY)
+    }
 
-        (c_price, end)
+    /// Returns the lowest charge power (kW) allowed within a charge block if the import limit
+    /// reduces charging in any of its quarters
+    ///
+    /// # Arguments
+    ///
+    /// * 'start' - the charge block starting hour
+    /// * 'size' - length of charge block
+    fn charge_kw_limit(&self, start: usize, size: usize) -> Option<f64> {
+        (start..start + size)
+            .map(|i| self.charge_limit(i))
+            .filter(|&limit| limit < self.charge_kwh_instance)
+            .min_by(|a, b| a.total_cmp(b))
+            .map(|limit| limit * 4.0)
+    }
+
+    /// Calculates the cost for charging from grid during one quarter, including any marginal
+    /// power fee the charge adds on top of the household consumption
+    ///
+    /// # Arguments
+    ///
+    /// * 'idx' - index of the time instance
+    /// * 'charge' - charge from grid in kWh
+    fn charge_quarter_cost(&self, idx: usize, charge: f64) -> f64 {
+        self.tariffs[idx] * charge + self.peak_cost(idx, self.cons[idx] + charge) - self.peak_cost(idx, self.cons[idx])
+    }
+
+    /// Calculates the cost for importing energy from the grid during one quarter
+    ///
+    /// # Arguments
+    ///
+    /// * 'idx' - index of the time instance
+    /// * 'import' - energy imported in kWh
+    fn import_cost(&self, idx: usize, import: f64) -> f64 {
+        self.tariffs[idx] * import + self.peak_cost(idx, import)
+    }
+
+    /// Calculates the marginal power fee for importing energy during one quarter.
+    /// A new hourly peak raises the monthly average of the highest peaks by its excess over the
+    /// current peak level divided by the number of peaks averaged. Since the fee is based on
+    /// hourly averages, each quarter is attributed a fourth of its own excess, which is exact when
+    /// all quarters of an hour exceed the peak level and conservative otherwise. Quarters in a
+    /// month after the current one start from a peak level of zero since no peaks are recorded yet.
+    ///
+    /// # Arguments
+    ///
+    /// * 'idx' - index of the time instance
+    /// * 'import' - energy imported in kWh
+    fn peak_cost(&self, idx: usize, import: f64) -> f64 {
+        match (&self.power_fee, self.peak_levels[idx]) {
+            (Some(pf), Some(peak_kw)) => {
+                let excess_kw = (import * 4.0 - peak_kw).max(0.0);
+                pf.sek_kw / pf.peaks_averaged.max(1) as f64 * excess_kw / 4.0
+            },
+            _ => 0.0,
+        }
     }
 
     /// Creates a charge block
@@ -439,6 +615,25 @@ impl<'a> Schedule<'a> {
             cost,
             charge_in,
             charge_out,
+            charge_kw_limit: self.charge_kw_limit(start, size),
+        }
+    }
+
+    /// Calculates the cost (negative when it is a revenue) for one quarter of forced discharge.
+    /// The energy delivered from the battery first covers any household net consumption, the rest
+    /// is exported, up to the grid export limit, and valued at the sell price.
+    ///
+    /// # Arguments
+    ///
+    /// * 'idx' - index of the time instance
+    /// * 'discharge' - energy drawn from the battery in kWh
+    fn sell_cost(&self, idx: usize, discharge: f64) -> f64 {
+        let grid = discharge * self.discharge_efficiency + self.net_prod[idx];
+
+        if grid >= 0.0 {
+            -grid.min(self.export_limit()) * self.sell_tariffs[idx]
+        } else {
+            self.import_cost(idx, -grid)
         }
     }
 
@@ -471,6 +666,7 @@ impl<'a> Schedule<'a> {
             next_start: pm_use.start + pm_use.size,
             next_charge_in: pm_use.charge_out,
             total_cost: pm_hold.cost + pm_use.cost,
+            residual_value: 0.0,
             blocks,
         })
     }
@@ -488,6 +684,7 @@ impl<'a> Schedule<'a> {
             cost: pm.cost,
             charge_in: pm.charge_in,
             charge_out: pm.charge_out,
+            charge_kw_limit: None,
         }
     }
 
@@ -514,7 +711,7 @@ impl<'a> Schedule<'a> {
         if block_type == BlockType::Charge {
             pm.cost = self.cons[start..end].iter()
                 .enumerate()
-                .map(|(i, &c)| self.tariffs[i + start] * c)
+                .map(|(i, &c)| self.import_cost(i + start, c))
                 .sum::<f64>();
         } else {
             self.net_prod[start..end].iter()
@@ -537,18 +734,19 @@ impl<'a> Schedule<'a> {
         // If net production is negative, we will potentially draw power from the battery and thus
         // need to consider the efficiency of transforming battery stored energy into household energy
         //let efficiency: f64 = if np_item < 0.0 { self.discharge_efficiency } else { 1.0 / self.charge_efficiency };
-        let tariff = self.tariffs[np_idx];
+        let sell_tariff = self.sell_tariffs[np_idx];
 
         // If we are in mode scheduler mode, and the block type is Hold, we are not going to use any
         // power from the battery even if we during the Hold block has accumulated power above the
         // hold level. This is how Backup mode is implemented in Fox ESS inverter. Hence, we always
         // have a cost when net production is negative, and always add power to the battery when net production
-        // is positive (unless the battery is full).
+        // is positive (unless the battery is full, in which case the surplus is exported).
         if self.mode_scheduler && pm.block_type == BlockType::Hold {
             if np_item < 0.0 {
-                pm.cost += tariff * (-np_item);
+                pm.cost += self.import_cost(np_idx, -np_item);
             } else {
                 let new_charge = pm.charge_out + np_item * self.charge_efficiency;
+                pm.cost -= sell_tariff * self.export_surplus(new_charge);
                 pm.charge_out = new_charge.min(self.bat_kwh);
             }
             return;
@@ -577,9 +775,11 @@ impl<'a> Schedule<'a> {
             // Since we don't have to buy from the grid taken efficiency of discharging from the battery
             // into account, we have to convert the shortfall back before calculating the cost. we divided
             // by efficiency previously, so to convert back we multiply by efficiency.
-            pm.cost += tariff * shortfall_batt * self.discharge_efficiency;
+            pm.cost += self.import_cost(np_idx, shortfall_batt * self.discharge_efficiency);
             pm.charge_out = pm.hold_level;
         } else {
+            // Whatever doesn't fit in the battery is exported to the grid and credited at the sell price
+            pm.cost -= sell_tariff * self.export_surplus(expected_charge_out);
             pm.charge_out = expected_charge_out.min(self.bat_kwh);
         }
 
@@ -606,6 +806,28 @@ impl<'a> Schedule<'a> {
         */
     }
 
+    /// Returns the energy exported to the grid, i.e. the part of an expected battery charge that
+    /// doesn't fit in the battery. The surplus is converted back from battery side to grid side
+    /// since we multiplied by charge efficiency when adding it. Surplus above the grid export
+    /// limit is curtailed.
+    ///
+    /// # Arguments
+    ///
+    /// * 'expected_charge' - the charge the battery would have if it had unlimited capacity
+    fn export_surplus(&self, expected_charge: f64) -> f64 {
+        ((expected_charge - self.bat_kwh).max(0.0) / self.charge_efficiency).min(self.export_limit())
+    }
+
+    /// Returns the value of energy left in the battery at the end of the schedule, i.e. what it
+    /// would cost to buy the energy the remaining charge can deliver to the household.
+    ///
+    /// # Arguments
+    ///
+    /// * 'charge_out' - the charge left in the battery
+    fn residual_value(&self, charge_out: f64) -> f64 {
+        (charge_out * self.discharge_efficiency * self.terminal_value * 100.0).round() / 100.0
+    }
+
     /// Returns the best block collection compared between the latest results and the stored best
     ///
     /// # Arguments
@@ -628,10 +850,11 @@ impl<'a> Schedule<'a> {
         }
 
         total_cost = (total_cost * 100.0).round() / 100.0;
+        let net_cost = total_cost - self.residual_value(next_charge_in);
 
-        if total_cost < best_blocks.total_cost {
+        if net_cost < best_blocks.net_cost() {
             self.collect_blocks(quad, self.schedule_length, next_charge_in, total_cost, pm)
-        } else if total_cost == best_blocks.total_cost {
+        } else if net_cost == best_blocks.net_cost() {
             num_blocks += quad.iter().map(|b| b.blocks.len()).sum::<usize>();
             if num_blocks < best_blocks.blocks.len() {
                 self.collect_blocks(quad, self.schedule_length, next_charge_in, total_cost, pm)
@@ -657,6 +880,7 @@ impl<'a> Schedule<'a> {
             next_start,
             next_charge_in,
             total_cost,
+            residual_value: self.residual_value(next_charge_in),
             blocks: quad.iter().map(|b| b.blocks.clone()).flatten().collect(),
         };
         if let Some(pm) = pm {
@@ -674,42 +898,23 @@ impl<'a> Schedule<'a> {
 /// * 'blocks' - a vector of temporary internal blocks
 /// * 'pre_blocks' - the number of blocks that has been skipped
 /// * 'soc_kwh' - kWh per soc used to convert from charge to State of Charge
-/// * 'date_time' - the date and time to be used to convert from hours to datetime in local TZ
+/// * 'date_time' - the date and time of the schedule start, i.e. of the first block after the skipped ones
 fn create_result_blocks(blocks: Vec<BlockInternal>, pre_blocks: usize, soc_kwh: f64, date_time: DateTime<Utc>) -> Vec<Block> {
     let mut result: Vec<Block> = Vec::new();
-    let time = date_time.duration_trunc(TimeDelta::days(1)).unwrap();
-    let offset = ((date_time - time).num_minutes() / 15) as usize;
 
     for b in blocks {
-        let mut start_time = time;
-        let mut end_time = time;
-
-        let mut start_hour = (b.start_hour - pre_blocks + offset) / 4;
-        let start_minute = (b.start_hour - pre_blocks + offset) % 4 * 15;
-        if start_hour > 23 {
-            start_hour -= 24;
-            start_time = start_time.add(TimeDelta::days(1));
-        }
-
-        let mut end_hour = ((b.start_hour - pre_blocks + offset) + b.size - 1) / 4;
-        let end_minute = ((b.start_hour - pre_blocks + offset) + b.size - 1) % 4 * 15;
-        if end_hour > 23 {
-            end_hour -= 24;
-            end_time = end_time.add(TimeDelta::days(1));
-        }
-
-        start_time = start_time.with_hour(start_hour as u32).unwrap().with_minute(start_minute as u32).unwrap();
-        end_time = end_time.with_hour(end_hour as u32).unwrap().with_minute(end_minute as u32).unwrap();
+        let start_time = date_time.add(TimeDelta::minutes((b.start_hour - pre_blocks) as i64 * 15));
+        let end_time = start_time.add(TimeDelta::minutes((b.size as i64 - 1) * 15));
 
         result.push(Block {
-            block_id: start_time.with_timezone(&Utc).timestamp() as usize,
+            block_id: start_time.timestamp() as usize,
             block_type: b.block_type.clone(),
             start_time,
             end_time,
-            start_hour,
-            start_minute,
-            end_hour,
-            end_minute,
+            start_hour: start_time.hour() as usize,
+            start_minute: start_time.minute() as usize,
+            end_hour: end_time.hour() as usize,
+            end_minute: end_time.minute() as usize,
             size: b.size,
             cost: b.cost,
             charge_in: b.charge_in,
@@ -718,6 +923,8 @@ fn create_result_blocks(blocks: Vec<BlockInternal>, pre_blocks: usize, soc_kwh:
             soc_in: 10 + (b.charge_in / soc_kwh).round().min(90.0) as usize,
             soc_out: 10 + (b.charge_out / soc_kwh).round().min(90.0) as usize,
             soc_kwh,
+            charge_kw_limit: b.charge_kw_limit,
+            estimated: false,
             status: Status::Waiting,
         });
     }
@@ -733,3 +940,144 @@ pub enum SchedulerError {
     #[error("InconsistentInputDataLength")]
     InconsistentInputDataLength,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(block_type: BlockType, start_hour: usize, size: usize) -> BlockInternal {
+        BlockInternal {
+            block_type,
+            start_hour,
+            size,
+            cost: 0.0,
+            charge_in: 0.0,
+            charge_out: 0.0,
+            charge_kw_limit: None,
+        }
+    }
+
+    fn config(optimizer: Optimizer) -> Config {
+        let mut config: Config = toml::from_str(include_str!("../../config/config.toml")).unwrap();
+        config.scheduler.optimizer = optimizer;
+        config.scheduler.min_saving = 0.0;
+        config
+    }
+
+    fn preformatted(tariffs: Vec<f64>, prod: &[f64], cons: f64) -> PreformattedData {
+        PreformattedData {
+            sell_tariffs: vec![0.1; tariffs.len()],
+            cons: vec![cons; tariffs.len()],
+            net_prod: prod.iter().map(|p| p - cons).collect(),
+            tariffs,
+        }
+    }
+
+    fn run(config: &Config, pd: &PreformattedData, soc_in: u8) -> SchedulerResult {
+        let start = DateTime::parse_from_rfc3339("2025-10-22T22:00:00Z").unwrap().with_timezone(&Utc);
+        Schedule::new(config, 100, 0.0, 0.0).update_scheduling(pd, soc_in, start, start)
+    }
+
+    /// Three cheap valleys, each followed by an expensive peak the battery can only cover once
+    fn three_valleys() -> Vec<f64> {
+        (0..96).map(|q| if q % 32 < 12 { 0.2 } else { 3.0 }).collect()
+    }
+
+    #[test]
+    fn result_blocks_span_beyond_the_next_day() {
+        let schedule_start = DateTime::parse_from_rfc3339("2025-10-22T22:00:00Z").unwrap().with_timezone(&Utc);
+        let blocks = vec![block(BlockType::Use, 4, 96), block(BlockType::Charge, 100, 92)];
+
+        let result = create_result_blocks(blocks, 4, 0.15, schedule_start);
+
+        assert_eq!(result[0].start_time, schedule_start);
+        assert_eq!(result[0].end_time, schedule_start + TimeDelta::minutes(95 * 15));
+        assert_eq!(result[1].start_time, schedule_start + TimeDelta::hours(24));
+        assert_eq!(result[1].end_time, schedule_start + TimeDelta::minutes(187 * 15));
+        assert_eq!((result[1].end_hour, result[1].end_minute), (20, 45));
+    }
+
+    #[test]
+    fn dynamic_programming_charges_in_every_valley() {
+        let config = config(Optimizer::DynamicProgramming);
+        let pd = preformatted(three_valleys(), &[0.0; 96], 0.6);
+
+        let types = run(&config, &pd, 10).blocks.into_iter()
+            .map(|b| b.block_type)
+            .filter(|t| *t != BlockType::Hold)
+            .collect::<Vec<BlockType>>();
+
+        assert_eq!(types, vec![
+            BlockType::Charge, BlockType::Use,
+            BlockType::Charge, BlockType::Use,
+            BlockType::Charge, BlockType::Use,
+        ]);
+    }
+
+    #[test]
+    fn dynamic_programming_is_never_worse_than_search() {
+        let cases = [
+            vec![0.5, 0.1, 2.0, 2.5, 0.2, 0.3, 3.0, 1.0],
+            vec![1.0, 1.0, 1.0, 0.1, 1.0, 4.0, 4.0, 0.5],
+            vec![3.0, 0.2, 0.2, 3.0, 3.0, 0.2, 3.0, 3.0],
+        ];
+        let prod = [0.0, 0.0, 0.5, 1.5, 0.8, 0.0, 0.0, 0.0];
+
+        // The search counts a charge as done when less than 0.05 kWh is left to charge, so let any
+        // charge fit within one quarter to compare the optimizers rather than that rounding
+        let configs = [Optimizer::Search, Optimizer::DynamicProgramming].map(|optimizer| {
+            let mut config = config(optimizer);
+            config.charge.charge_kwh_hour = 5.0;
+            config
+        });
+
+        for tariffs in cases {
+            let pd = preformatted(tariffs, &prod, 0.8);
+            let search = run(&configs[0], &pd, 30);
+            let dynamic = run(&configs[1], &pd, 30);
+
+            assert!(dynamic.total_cost - dynamic.residual_value <= search.total_cost - search.residual_value + 0.01);
+        }
+    }
+
+    #[test]
+    fn dynamic_programming_keeps_charge_within_bounds() {
+        let config = config(Optimizer::DynamicProgramming);
+        let bat_kwh = config.charge.bat_capacity_kwh * 0.9;
+        let prod = (0..96).map(|q| if (40..64).contains(&q) { 2.0 } else { 0.0 }).collect::<Vec<f64>>();
+        let pd = preformatted(three_valleys(), &prod, 0.6);
+
+        let blocks = run(&config, &pd, 60).blocks;
+
+        for b in &blocks {
+            assert!(b.charge_in >= -1e-9 && b.charge_in <= bat_kwh + 1e-9);
+            assert!(b.charge_out >= -1e-9 && b.charge_out <= bat_kwh + 1e-9);
+            assert!((10..=100).contains(&b.soc_in) && (10..=100).contains(&b.soc_out));
+        }
+        for pair in blocks.windows(2) {
+            assert!((pair[0].charge_out - pair[1].charge_in).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn dynamic_programming_costs_hold_blocks_at_their_charge_in() {
+        let mut config = config(Optimizer::DynamicProgramming);
+        config.scheduler.mode_scheduler = false;
+        let prod = (0..96).map(|q| if (40..44).contains(&q) || (48..52).contains(&q) { 1.0 } else { 0.0 }).collect::<Vec<f64>>();
+        let tariffs = (0..96).map(|q| if (64..80).contains(&q) { 4.0 } else { 1.0 }).collect::<Vec<f64>>();
+        let pd = preformatted(tariffs, &prod, 0.6);
+        let start = DateTime::parse_from_rfc3339("2025-10-22T22:00:00Z").unwrap().with_timezone(&Utc);
+        let mut schedule = Schedule::new(&config, 100, 0.0, 0.0);
+
+        let blocks = schedule.update_scheduling(&pd, 60, start, start).blocks;
+        let holds = blocks.iter().filter(|b| b.block_type == BlockType::Hold).collect::<Vec<&Block>>();
+
+        assert!(holds.iter().any(|b| b.size > 1));
+        for b in holds {
+            let start_idx = (b.start_time - start).num_minutes() as usize / 15;
+            let pm = schedule.update_for_pv(BlockType::Hold, start_idx, start_idx + b.size, b.charge_in);
+            assert!((pm.cost - b.cost).abs() < 1e-9);
+            assert!((pm.charge_out - b.charge_out).abs() < 1e-9);
+        }
+    }
+}
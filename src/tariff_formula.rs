@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Datelike, Local, NaiveDate, Timelike, Utc};
+use crate::calendar::is_swedish_holiday;
+use crate::config::{ComponentKind, TariffComponent, TariffFees, TariffFormula, TariffSide, VariableFeeRule};
+use crate::models::TariffValue;
+
+/// Computes buy and sell prices from spot prices according to a tariff formula
+pub struct PriceFormula {
+    buy_vat: f64,
+    sell_vat: f64,
+    components: Vec<TariffComponent>,
+    variable_fee: f64,
+    variable_fee_rules: Vec<VariableFeeRule>,
+}
+
+impl PriceFormula {
+    /// Creates a price formula from the configured tariff formula, or from the Swedish default
+    /// profile built from the tariff fees if no formula is configured
+    ///
+    /// # Arguments
+    ///
+    /// * 'fees' - tariff fees, gives the variable fee and its time-of-use rules
+    /// * 'formula' - optional tariff formula
+    pub fn new(fees: &TariffFees, formula: &Option<TariffFormula>) -> PriceFormula {
+        let formula = formula.clone().unwrap_or_else(|| swedish_formula(fees));
+
+        Self {
+            buy_vat: formula.buy_vat_percentage / 100.0,
+            sell_vat: formula.sell_vat_percentage / 100.0,
+            components: formula.components,
+            variable_fee: fees.variable_fee,
+            variable_fee_rules: fees.variable_fee_rules.clone(),
+        }
+    }
+
+    /// Adds VAT and other markups such as energy taxes etc.
+    ///
+    /// # Arguments
+    ///
+    /// * 'day_avg' - average spot price for the day in SEK/kWh
+    /// * 'price' - spot price in SEK/kWh
+    /// * 'delivery_start' - start time for the spot
+    pub fn tariff_value(&self, day_avg: f64, price: f64, delivery_start: DateTime<Utc>) -> TariffValue {
+        let variable_fee = self.variable_fee_at(delivery_start);
+
+        let buy = self.sum_components(TariffSide::Buy, day_avg, price, variable_fee) * (1.0 + self.buy_vat);
+        let sell = self.sum_components(TariffSide::Sell, day_avg, price, variable_fee) * (1.0 + self.sell_vat);
+
+        TariffValue {
+            valid_time: delivery_start,
+            price: round_to_two_decimals(price),
+            buy: round_to_two_decimals(buy),
+            sell: round_to_two_decimals(sell),
+            variable_fee,
+            estimated: false,
+        }
+    }
+
+    /// Computes tariff values for spot prices within the given period, where the day average
+    /// is calculated per local day over all given prices
+    ///
+    /// # Arguments
+    ///
+    /// * 'prices' - spot prices in currency/kWh per quarter
+    /// * 'day_start' - start of period to compute tariff values for
+    /// * 'day_end' - end of period to compute tariff values for (non-inclusive)
+    pub fn tariff_values(&self, prices: Vec<(DateTime<Utc>, f64)>, day_start: DateTime<Utc>, day_end: DateTime<Utc>) -> Vec<TariffValue> {
+        let mut days: HashMap<NaiveDate, (f64, f64)> = HashMap::new();
+        for (delivery_start, price) in prices.iter() {
+            let _ = days
+                .entry(delivery_start.with_timezone(&Local).date_naive())
+                .and_modify(|v| { v.0 += price; v.1 += 1.0; })
+                .or_insert((*price, 1.0));
+        }
+
+        prices.into_iter()
+            .filter(|(delivery_start, _)| *delivery_start >= day_start && *delivery_start < day_end)
+            .map(|(delivery_start, price)| {
+                let (sum, count) = days[&delivery_start.with_timezone(&Local).date_naive()];
+                self.tariff_value(sum / count, price, delivery_start)
+            })
+            .collect()
+    }
+
+    /// Sums all components (SEK/kWh, exl. VAT) that apply to the given side
+    ///
+    /// # Arguments
+    ///
+    /// * 'side' - buy or sell
+    /// * 'day_avg' - average spot price for the day in SEK/kWh
+    /// * 'price' - spot price in SEK/kWh
+    /// * 'variable_fee' - the resolved variable fee in öre/kWh
+    fn sum_components(&self, side: TariffSide, day_avg: f64, price: f64, variable_fee: f64) -> f64 {
+        self.components.iter()
+            .filter(|c| c.applies_to == side || c.applies_to == TariffSide::Both)
+            .map(|c| match c.kind {
+                ComponentKind::Fixed => c.value / 100.0,
+                ComponentKind::SpotPercentage => c.value / 100.0 * price,
+                ComponentKind::DayAveragePercentage => c.value / 100.0 * day_avg,
+                ComponentKind::VariableFee => variable_fee / 100.0,
+            })
+            .sum()
+    }
+
+    /// Returns the variable grid fee (öre/kWh, exl. VAT) in force at the given time, i.e. the fee
+    /// of the first matching time-of-use rule or the default variable fee if no rule matches
+    ///
+    /// # Arguments
+    ///
+    /// * 'date_time' - the time to resolve the fee for
+    fn variable_fee_at(&self, date_time: DateTime<Utc>) -> f64 {
+        let local = date_time.with_timezone(&Local);
+
+        self.variable_fee_rules.iter()
+            .find(|r| {
+                (r.months.is_empty() || r.months.contains(&local.month())) &&
+                    (r.weekdays.is_empty() || r.weekdays.contains(&local.weekday().number_from_monday())) &&
+                    (r.hours.is_empty() || r.hours.iter().any(|(start, end)| local.hour() >= *start && local.hour() < *end)) &&
+                    !(r.exclude_holidays && is_swedish_holiday(local.date_naive()))
+            })
+            .map(|r| r.fee)
+            .unwrap_or(self.variable_fee)
+    }
+}
+
+/// Builds the Swedish default tariff formula from the tariff fees, i.e. grid and trading fees
+/// plus spot price with 25% VAT when buying, and spot price plus production price without VAT
+/// when selling
+///
+/// # Arguments
+///
+/// * 'fees' - tariff fees
+fn swedish_formula(fees: &TariffFees) -> TariffFormula {
+    let component = |kind: ComponentKind, value: f64, applies_to: TariffSide| TariffComponent {
+        kind,
+        value,
+        applies_to,
+    };
+
+    TariffFormula {
+        buy_vat_percentage: 25.0,
+        sell_vat_percentage: 0.0,
+        components: vec![
+            component(ComponentKind::SpotPercentage, 100.0, TariffSide::Both),
+            component(ComponentKind::VariableFee, 0.0, TariffSide::Buy),
+            component(ComponentKind::Fixed, fees.energy_tax, TariffSide::Buy),
+            component(ComponentKind::DayAveragePercentage, fees.spot_fee_percentage, TariffSide::Buy),
+            component(ComponentKind::Fixed, fees.swedish_power_grid, TariffSide::Buy),
+            component(ComponentKind::Fixed, fees.balance_responsibility, TariffSide::Buy),
+            component(ComponentKind::Fixed, fees.electric_certificate, TariffSide::Buy),
+            component(ComponentKind::Fixed, fees.guarantees_of_origin, TariffSide::Buy),
+            component(ComponentKind::Fixed, fees.fixed, TariffSide::Buy),
+            component(ComponentKind::Fixed, fees.production_price, TariffSide::Sell),
+        ],
+    }
+}
+
+/// Rounds values to two decimals
+///
+/// # Arguments
+///
+/// * 'price' - the price to round to two decimals
+fn round_to_two_decimals(price: f64) -> f64 {
+    (price * 100f64).round() / 100f64
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use super::*;
+
+    fn fees() -> TariffFees {
+        toml::from_str(r#"
+            variable_fee           = 25.30
+            spot_fee_percentage    = 7.7
+            energy_tax             = 36.0
+            swedish_power_grid     = 1.833
+            balance_responsibility = 0.80
+            electric_certificate   = 1.60
+            guarantees_of_origin   = 3.00
+            fixed                  = 2.40
+            production_price       = 7.5
+        "#).unwrap()
+    }
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap().with_timezone(&Utc)
+    }
+
+    /// Winter weekday daytime fee except holidays, and a flat April fee
+    fn time_of_use_formula() -> PriceFormula {
+        let mut fees = fees();
+        fees.variable_fee_rules = vec![
+            VariableFeeRule { fee: 76.5, months: vec![1, 2, 3, 11, 12], weekdays: vec![1, 2, 3, 4, 5], hours: vec![(6, 22)], exclude_holidays: true },
+            VariableFeeRule { fee: 50.0, months: vec![4], weekdays: Vec::new(), hours: Vec::new(), exclude_holidays: false },
+        ];
+        PriceFormula::new(&fees, &None)
+    }
+
+    #[test]
+    fn time_of_use_rules_match_weekdays_and_hours() {
+        let formula = time_of_use_formula();
+
+        assert_eq!(formula.variable_fee_at(local(2025, 1, 8, 10, 0)), 76.5);  // Wednesday
+        assert_eq!(formula.variable_fee_at(local(2025, 1, 8, 6, 0)), 76.5);
+        assert_eq!(formula.variable_fee_at(local(2025, 1, 8, 5, 45)), 25.3);
+        assert_eq!(formula.variable_fee_at(local(2025, 1, 8, 22, 0)), 25.3);  // Window end is non-inclusive
+        assert_eq!(formula.variable_fee_at(local(2025, 1, 11, 10, 0)), 25.3); // Saturday
+        assert_eq!(formula.variable_fee_at(local(2025, 1, 12, 10, 0)), 25.3); // Sunday
+    }
+
+    #[test]
+    fn time_of_use_rules_exclude_holidays() {
+        let formula = time_of_use_formula();
+
+        assert_eq!(formula.variable_fee_at(local(2025, 1, 6, 10, 0)), 25.3);   // Trettondedag jul, a Monday
+        assert_eq!(formula.variable_fee_at(local(2025, 12, 24, 10, 0)), 25.3); // Julafton, a Wednesday
+        assert_eq!(formula.variable_fee_at(local(2025, 12, 31, 10, 0)), 25.3); // Nyårsafton, a Wednesday
+        assert_eq!(formula.variable_fee_at(local(2025, 12, 23, 10, 0)), 76.5);
+    }
+
+    #[test]
+    fn time_of_use_rules_match_local_months() {
+        let formula = time_of_use_formula();
+
+        assert_eq!(formula.variable_fee_at(local(2025, 3, 31, 21, 45)), 76.5);
+        assert_eq!(formula.variable_fee_at(local(2025, 3, 31, 23, 45)), 25.3);
+        assert_eq!(formula.variable_fee_at(local(2025, 4, 1, 0, 0)), 50.0);
+        assert_eq!(formula.variable_fee_at(local(2025, 4, 30, 23, 45)), 50.0);
+        assert_eq!(formula.variable_fee_at(local(2025, 10, 31, 10, 0)), 25.3);
+        assert_eq!(formula.variable_fee_at(local(2025, 11, 3, 10, 0)), 76.5);
+    }
+
+    #[test]
+    fn default_formula_reproduces_baseline_prices() {
+        let fees = fees();
+        let formula = PriceFormula::new(&fees, &None);
+        let delivery_start = DateTime::parse_from_rfc3339("2025-10-22T10:00:00Z").unwrap().with_timezone(&Utc);
+
+        for (day_avg, price) in [(0.85, 0.4321), (0.85, 1.9876), (0.12, -0.0523), (2.5, 0.0)] {
+            let grid_fees = (fees.variable_fee + fees.energy_tax) / 100.0 + fees.spot_fee_percentage / 100.0 * day_avg;
+            let trade_fees = (fees.swedish_power_grid + fees.balance_responsibility + fees.electric_certificate +
+                fees.guarantees_of_origin + fees.fixed) / 100.0 + price;
+            let buy = round_to_two_decimals((grid_fees + trade_fees) / 0.8);
+            let sell = round_to_two_decimals(fees.production_price / 100.0 + price);
+
+            let tariff = formula.tariff_value(day_avg, price, delivery_start);
+
+            assert_eq!((tariff.buy, tariff.sell), (buy, sell));
+            assert_eq!(tariff.variable_fee, fees.variable_fee);
+        }
+    }
+}
@@ -2,16 +2,20 @@ use std::{fs, thread};
 use std::ops::Add;
 use chrono::{DateTime, Duration, DurationRound, Local, NaiveDateTime, TimeDelta, Timelike, Utc};
 use glob::glob;
-use log::info;
+use log::{info, warn};
 use anyhow::Result;
 use foxess::FoxVariables;
 use thiserror::Error;
-use crate::config::{Config, Files};
+use crate::config::{Config, Files, TerminalValue};
 use crate::initialization::Mgr;
-use crate::models::{BaseData, MinuteValues, TariffFees};
+use crate::models::{BaseData, MinuteValues, RetryHint, TariffFees, TariffValue};
+use crate::power_peaks::PowerPeaks;
 use crate::{retry, wrapper};
 use crate::scheduler::{Schedule, SchedulerResult};
 
+/// Minutes to wait before running again after a provisional schedule
+const RETRY_AFTER_MINUTES: i64 = 30;
+
 /// Runs a schedule creation process
 ///
 /// # Arguments
@@ -53,16 +57,32 @@ pub fn run(config: &Config, mgr: &mut Mgr, files: &Files, debug_run_time: Option
         (soc, soh)
     };
 
+    // Get prices, falling back to a provisional schedule on a partial horizon if prices for
+    // the whole horizon are not yet published
+    let (mut run_schema, tariffs, provisional) = get_tariffs(mgr, run_schema)?;
+
+    // Extend the horizon with estimated prices if price forecasting is configured
+    if !provisional && mgr.prices.forecast_hours() > 0 {
+        run_schema.schedule_day_end = run_schema.schedule_day_end.add(TimeDelta::hours(mgr.prices.forecast_hours()));
+    }
+
     // Calculate the new schedule
-    let (scheduler_result, base_data) = get_schedule(&config, mgr, start_soc, soh, &run_schema)?;
+    let (mut scheduler_result, base_data) = get_schedule(config, mgr, start_soc, soh, &run_schema, tariffs)?;
+    scheduler_result.provisional = provisional;
 
-    info!("Base Cost: {}, Schedule Cost: {}", scheduler_result.base_cost, scheduler_result.total_cost);
+    info!("Base Cost: {}, Schedule Cost: {}, Residual Value: {}", scheduler_result.base_cost, scheduler_result.total_cost, scheduler_result.residual_value);
     for b in scheduler_result.blocks.iter() {
         info!("{}", b);
     }
 
+    let retry_hint = provisional.then(|| RetryHint {
+        retry_after: Utc::now().add(TimeDelta::minutes(RETRY_AFTER_MINUTES)),
+        reason: format!("prices not published beyond {}", scheduler_result.end_time),
+    });
+
     save_schedule(&files.schedule_dir, scheduler_result)?;
     save_base_data(&files.base_data_dir, &base_data)?;
+    save_retry_hint(&files.schedule_dir, retry_hint)?;
 
     Ok(())
 }
@@ -76,35 +96,49 @@ pub fn run(config: &Config, mgr: &mut Mgr, files: &Files, debug_run_time: Option
 /// * 'soc_in' - state of battery charge when going in to the schedule
 /// * 'soh' - battery's current state of health
 /// * 'run_schema' - a schema with a schedule for running the scheduler, and time converted to Utc
-fn get_schedule(config: &Config, mgr: &mut Mgr, soc_in: u8, soh: u8, run_schema: &RunSchema) -> Result<(SchedulerResult, BaseData), WorkerError> {
+/// * 'tariffs' - tariffs for the schedule
+fn get_schedule(config: &Config, mgr: &mut Mgr, soc_in: u8, soh: u8, run_schema: &RunSchema, tariffs: Vec<TariffValue>) -> Result<(SchedulerResult, BaseData), WorkerError> {
     let forecast = retry!(||mgr.forecast.new_forecast(run_schema.run_start, run_schema.schedule_day_end))
         .map_err(|e| WorkerError::GetScheduleError(format!("error getting forecast: {}", e.to_string())))?;
-    let pv_estimate = mgr.pv.estimate(&forecast, run_schema.run_start, run_schema.schedule_day_end)
+    let dc_estimate = mgr.pv.estimate(&forecast, run_schema.run_start, run_schema.schedule_day_end)
         .map_err(|e| WorkerError::GetScheduleError(format!("error estimating production: {}", e.to_string())))?;
     let cons_estimate = mgr.cons.estimate(&forecast, run_schema.local_offset);
+    let (pv_estimate, clipped_estimate) = mgr.pv.ac_power(dc_estimate);
+    let tariffs = mgr.prices.extend_with_forecast(tariffs, run_schema.schedule_day_end, &forecast);
 
     let production = MinuteValues::new(&pv_estimate, run_schema.run_start).time_groups(15, true);
     let consumption = MinuteValues::new(&cons_estimate, run_schema.run_start).time_groups(15, true);
-    let tariffs = retry!(||mgr.nordpool.get_tariffs(run_schema.run_start, run_schema.schedule_day_end))
-        .map_err(|e| WorkerError::GetScheduleError(format!("error getting tariffs: {}", e.to_string())))?;
+    let terminal_value = get_terminal_value(config, mgr, run_schema, &tariffs);
+    info!("Terminal value of residual battery energy: {:.2} SEK/kWh", terminal_value);
 
-    let mut scheduler = Schedule::new(config, soh);
+    let peak_kw = get_peak_level(config, mgr, run_schema);
+
+    let mut scheduler = Schedule::new(config, soh, terminal_value, peak_kw);
     let pd = Schedule::preformat_data(&tariffs, &production.data, &consumption.data, run_schema.run_start, run_schema.schedule_day_end)
         .map_err(|e| WorkerError::GetScheduleError(format!("error preformatting data: {}", e.to_string())))?;
     info!("Time blocks to schedule for: {}", pd.tariffs.len());
 
-    let sr = scheduler.update_scheduling(&pd.tariffs, &pd.cons, &pd.net_prod, soc_in, run_schema.run_start, run_schema.schedule_start);
+    let mut sr = scheduler.update_scheduling(&pd, soc_in, run_schema.run_start, run_schema.schedule_start);
+    sr.estimated_from = tariffs.iter().find(|t| t.estimated).map(|t| t.valid_time);
+    if let Some(estimated_from) = sr.estimated_from {
+        info!("Schedule uses estimated prices from {}", estimated_from);
+        sr.blocks.iter_mut()
+            .filter(|b| b.end_time >= estimated_from)
+            .for_each(|b| b.estimated = true);
+    }
 
     let base_data = BaseData {
         date_time: run_schema.schedule_start,
         base_cost: sr.base_cost,
         schedule_cost: sr.total_cost,
+        residual_value: sr.residual_value,
         soc_kwh: scheduler.soc_kwh,
         production: MinuteValues::new(&pv_estimate, run_schema.run_start).time_groups(5, false).data,
+        clipped: MinuteValues::new(&clipped_estimate, run_schema.run_start).time_groups(5, false).data,
         consumption: MinuteValues::new(&cons_estimate, run_schema.run_start).time_groups(5, false).data,
         forecast: forecast.forecast,
         tariffs,
-        tariff_fees: TariffFees {
+        tariff_fees: config.tariff_formula.is_none().then_some(TariffFees {
             variable_fee: config.tariff_fees.variable_fee,
             spot_fee_percentage: config.tariff_fees.spot_fee_percentage,
             energy_tax: config.tariff_fees.energy_tax,
@@ -114,12 +148,166 @@ fn get_schedule(config: &Config, mgr: &mut Mgr, soc_in: u8, soh: u8, run_schema:
             guarantees_of_origin: config.tariff_fees.guarantees_of_origin,
             fixed: config.tariff_fees.fixed,
             production_price: config.tariff_fees.production_price,
-        }
+        }),
     };
 
     Ok((sr, base_data))
 }
 
+/// Retrieves tariffs for the run schema. If prices for the whole schema are not available,
+/// e.g. since next day's prices are not yet published, a schema for the rest of the current
+/// day is tried instead and the result is flagged as provisional.
+///
+/// # Arguments
+///
+/// * 'mgr' - struct with managers
+/// * 'run_schema' - a schema with a schedule for running the scheduler, and time converted to Utc
+fn get_tariffs(mgr: &Mgr, run_schema: RunSchema) -> Result<(RunSchema, Vec<TariffValue>, bool), WorkerError> {
+    let error = match retry!(||mgr.prices.get_tariffs(run_schema.run_start, run_schema.schedule_day_end)) {
+        Ok(tariffs) => return Ok((run_schema, tariffs, false)),
+        Err(e) => e,
+    };
+
+    let (_, day_end) = get_utc_day_start(run_schema.run_start, 0);
+    let schedule_start = run_schema.run_start
+        .add(TimeDelta::hours(1))
+        .duration_trunc(TimeDelta::minutes(15))
+        .map_err(|e| WorkerError::RunSchemaError(format!("run_start date: {}", e)))?;
+
+    if day_end >= run_schema.schedule_day_end || schedule_start >= day_end {
+        return Err(WorkerError::GetScheduleError(format!("error getting tariffs: {}", error)));
+    }
+
+    let tariffs = mgr.prices.get_tariffs(run_schema.run_start, day_end)
+        .map_err(|e| WorkerError::GetScheduleError(format!("error getting tariffs: {} (partial horizon: {})", error, e)))?;
+
+    warn!("prices not available until {} ({}), making a provisional schedule until {}", run_schema.schedule_day_end, error, day_end);
+
+    Ok((
+        RunSchema {
+            run_start: run_schema.run_start,
+            schedule_start,
+            schedule_day_end: day_end,
+            prices_day_end: day_end,
+            local_offset: run_schema.local_offset,
+        },
+        tariffs,
+        true,
+    ))
+}
+
+/// Resolves the value (SEK/kWh) of energy left in the battery at the end of the schedule
+/// according to the configured method. Failing to get next day's prices or forecast is not
+/// an error, in that case the configured fallback value is used.
+///
+/// # Arguments
+///
+/// * 'config' - configuration
+/// * 'mgr' - struct with managers
+/// * 'run_schema' - a schema with a schedule for running the scheduler, and time converted to Utc
+/// * 'tariffs' - tariffs for the schedule, only published (not estimated) prices are averaged
+fn get_terminal_value(config: &Config, mgr: &Mgr, run_schema: &RunSchema, tariffs: &[TariffValue]) -> f64 {
+    let (_, next_day_end) = get_utc_day_start(run_schema.prices_day_end, 0);
+
+    match config.scheduler.terminal_value {
+        TerminalValue::None => 0.0,
+        TerminalValue::Fixed { sek_kwh } => sek_kwh,
+        TerminalValue::NextDayPrice { fraction, fallback_sek_kwh } => {
+            match mgr.prices.get_tariffs(run_schema.prices_day_end, next_day_end) {
+                Ok(next_day) if !next_day.is_empty() => fraction * next_day.iter().map(|t| t.buy).sum::<f64>() / next_day.len() as f64,
+                Ok(_) => {
+                    info!("next day prices are empty, using fallback terminal value");
+                    fallback_sek_kwh
+                },
+                Err(e) => {
+                    info!("next day prices not available ({}), using fallback terminal value", e);
+                    fallback_sek_kwh
+                }
+            }
+        },
+        TerminalValue::Forecast { fraction, fallback_sek_kwh } => {
+            match get_next_day_deficit_share(mgr, run_schema, next_day_end) {
+                Ok(share) => {
+                    let published = tariffs.iter().filter(|t| !t.estimated).map(|t| t.buy).collect::<Vec<f64>>();
+                    let avg_buy = published.iter().sum::<f64>() / published.len().max(1) as f64;
+                    fraction * avg_buy * share
+                },
+                Err(e) => {
+                    warn!("failed to estimate next day PV coverage ({}), using fallback terminal value", e);
+                    fallback_sek_kwh
+                }
+            }
+        },
+    }
+}
+
+/// Updates this month's power peaks record with measured grid import and returns the level
+/// (kW) a new hourly peak has to exceed to raise the monthly power fee. Failing to update the
+/// record is not an error, in that case the peaks recorded so far are used.
+///
+/// # Arguments
+///
+/// * 'config' - configuration
+/// * 'mgr' - struct with managers
+/// * 'run_schema' - a schema with a schedule for running the scheduler, and time converted to Utc
+fn get_peak_level(config: &Config, mgr: &Mgr, run_schema: &RunSchema) -> f64 {
+    let Some(power_fee) = &config.tariff_fees.power_fee else {
+        return 0.0;
+    };
+    let path = format!("{}power_peaks.json", config.files.base_data_dir);
+
+    let mut peaks = match PowerPeaks::load(&path, run_schema.run_start) {
+        Ok(peaks) => peaks,
+        Err(e) => {
+            warn!("failed to load power peaks: {}", e);
+            return 0.0;
+        }
+    };
+
+    match peaks.update(&mgr.fox, power_fee, run_schema.run_start) {
+        Ok(_) => {
+            if let Err(e) = peaks.save(&path) {
+                warn!("failed to save power peaks: {}", e);
+            }
+        },
+        Err(e) => warn!("failed to update power peaks: {}", e),
+    }
+
+    let peak_level = peaks.peak_level(power_fee.peaks_averaged);
+    info!("Power peak level this month: {:.2} kW", peak_level);
+
+    peak_level
+}
+
+/// Returns the share (0-1) of the estimated consumption the day after published prices that is
+/// not expected to be covered by PV production
+///
+/// # Arguments
+///
+/// * 'mgr' - struct with managers
+/// * 'run_schema' - a schema with a schedule for running the scheduler, and time converted to Utc
+/// * 'next_day_end' - end of the day following published prices (non-inclusive)
+fn get_next_day_deficit_share(mgr: &Mgr, run_schema: &RunSchema, next_day_end: DateTime<Utc>) -> Result<f64, WorkerError> {
+    let forecast = mgr.forecast.new_forecast(run_schema.prices_day_end, next_day_end)
+        .map_err(|e| WorkerError::GetScheduleError(format!("error getting forecast: {}", e)))?;
+    let dc_estimate = mgr.pv.estimate(&forecast, run_schema.prices_day_end, next_day_end)
+        .map_err(|e| WorkerError::GetScheduleError(format!("error estimating production: {}", e)))?;
+    let cons_estimate = mgr.cons.estimate(&forecast, run_schema.local_offset);
+    let (pv_estimate, _) = mgr.pv.ac_power(dc_estimate);
+
+    let cons = cons_estimate.iter().sum::<f64>();
+    let deficit = cons_estimate.iter()
+        .zip(pv_estimate.iter())
+        .map(|(c, p)| (c - p).max(0.0))
+        .sum::<f64>();
+
+    if cons > 0.0 {
+        Ok(deficit / cons)
+    } else {
+        Ok(0.0)
+    }
+}
+
 /// Creates a run schema to be used to calculate the SoC at the time of schedule start
 ///
 /// # Arguments
@@ -156,6 +344,7 @@ fn get_schedule_start_schema(run_start: DateTime<Local>) -> Result<RunSchema, Wo
         run_start: run_start_utc,
         schedule_start: schedule_start_utc,
         schedule_day_end: schedule_day_end_utc,
+        prices_day_end: schedule_day_end_utc,
         local_offset: run_start.offset().local_minus_utc() as i64,
     })
 }
@@ -182,6 +371,37 @@ fn save_schedule(path: &str, schedule: SchedulerResult) -> Result<(), WorkerErro
     Ok(())
 }
 
+/// Saves a retry hint telling that the scheduler should be run again, or removes any previous
+/// hint if the schedule is complete
+///
+/// # Arguments
+///
+/// * 'path' - path to the schedule directory
+/// * 'retry_hint' - the hint to save, or None to remove a previous hint
+fn save_retry_hint(path: &str, retry_hint: Option<RetryHint>) -> Result<(), WorkerError> {
+    let filename = format!("{}retry_after.json", path);
+
+    match retry_hint {
+        Some(hint) => {
+            let json = serde_json::to_string_pretty(&hint)
+                .map_err(|e| WorkerError::SaveScheduleError(format!("error serializing retry hint: {}", e)))?;
+
+            fs::write(&filename, json)
+                .map_err(|e| WorkerError::SaveScheduleError(format!("error writing retry hint to file: {}", e)))?;
+
+            info!("Retry hint saved to {}, retry after {}", filename, hint.retry_after);
+        },
+        None => {
+            if fs::exists(&filename).unwrap_or(false) {
+                fs::remove_file(&filename)
+                    .map_err(|e| WorkerError::SaveScheduleError(format!("error removing retry hint: {}", e)))?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
 /// Saves base data for use in e.g. MyGridDash
 ///
 /// # Arguments
@@ -257,6 +477,7 @@ struct RunSchema {
     run_start: DateTime<Utc>,
     schedule_start: DateTime<Utc>,
     schedule_day_end: DateTime<Utc>,   // Non-Inclusive
+    prices_day_end: DateTime<Utc>,     // Non-Inclusive, end of published prices before any extension with estimated prices
     local_offset: i64,
 }
 